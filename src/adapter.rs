@@ -0,0 +1,232 @@
+use crate::{Hyperv, HypervError, Result, VmId, ps_quote};
+use crate::network::normalize_mac;
+use serde_derive::Deserialize;
+use std::ops::RangeInclusive;
+
+const MAX_VLAN_ID: u16 = 4094;
+const MAX_IOV_WEIGHT: u32 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VlanMode {
+    // Traffic is not tagged
+    Untagged,
+    // All traffic is tagged with this VLAN
+    Access(u16),
+    // The guest tags traffic itself with any of the allowed VLANs. Untagged traffic goes to the native one
+    Trunk { native: u16, allowed: Vec<RangeInclusive<u16>> },
+    // Private VLAN modes that can only be set through Set-VMNetworkAdapterVlan directly
+    Other(String),
+}
+
+// Traffic of Source adapters is copied to the Destination adapters on the same switch,
+// e.g. a VM running a packet capture appliance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortMirroring {
+    None,
+    Source,
+    Destination,
+}
+
+// Rates in bytes per second. None means no reservation or no limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bandwidth {
+    pub minimum_bytes_per_sec: Option<u64>,
+    pub maximum_bytes_per_sec: Option<u64>,
+}
+
+// Hyper-V works in bits per second and uses 0 for no setting
+#[derive(Deserialize)]
+struct RawBandwidth {
+    #[serde(rename = "MinimumBandwidthAbsolute")]
+    minimum: Option<u64>,
+    #[serde(rename = "MaximumBandwidth")]
+    maximum: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RawVlan {
+    #[serde(rename = "OperationMode")]
+    mode: String,
+    #[serde(rename = "AccessVlanId")]
+    access_vlan_id: u16,
+    #[serde(rename = "NativeVlanId")]
+    native_vlan_id: u16,
+    #[serde(rename = "AllowedVlanIdListString")]
+    allowed: Option<String>,
+}
+
+impl Hyperv {
+    pub fn get_adapter_vlan(id: &VmId, adapter_name: &str) -> Result<VlanMode> {
+        let command = format!(
+            "{} | get-vmnetworkadaptervlan -ErrorAction Stop |
+                select-object -property @{{Name='OperationMode';Expression={{$_.OperationMode.ToString()}}}},AccessVlanId,NativeVlanId,AllowedVlanIdListString | convertto-json",
+            adapter(id, adapter_name));
+        let raw: Option<RawVlan> = Self::spawn_and_deserialize(&command)?;
+        let raw = raw.ok_or_else(|| HypervError::new("Get-VMNetworkAdapterVlan returned no output"))?;
+
+        Ok(match raw.mode.as_str() {
+            "Untagged" => VlanMode::Untagged,
+            "Access" => VlanMode::Access(raw.access_vlan_id),
+            "Trunk" => VlanMode::Trunk { native: raw.native_vlan_id, allowed: parse_vlan_list(raw.allowed.as_ref().map_or("", String::as_str))? },
+            mode => VlanMode::Other(mode.to_owned()),
+        })
+    }
+
+    pub fn set_adapter_vlan(id: &VmId, adapter_name: &str, mode: &VlanMode) -> Result<()> {
+        let params = match mode {
+            VlanMode::Untagged => "-Untagged".to_owned(),
+            VlanMode::Access(vlan) => format!("-Access -VlanId {}", check_vlan_id(*vlan)?),
+            VlanMode::Trunk { native, allowed } => {
+                if allowed.is_empty() {
+                    return Err(HypervError::new("A trunk must allow at least one VLAN"))
+                }
+                for range in allowed {
+                    if range.start() > range.end() {
+                        return Err(HypervError::new(format!("Invalid VLAN range {}-{}", range.start(), range.end())))
+                    }
+                    check_vlan_id(*range.start())?;
+                    check_vlan_id(*range.end())?;
+                }
+                format!("-Trunk -NativeVlanId {} -AllowedVlanIdList '{}'", check_vlan_id(*native)?, format_vlan_list(allowed))
+            },
+            VlanMode::Other(mode) => return Err(HypervError::new(format!("VLAN mode '{}' cannot be set through this API", mode))),
+        };
+
+        let command = format!("{} | set-vmnetworkadaptervlan {} -ErrorAction Stop", adapter(id, adapter_name), params);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Accepts the MAC in any of the forms normalize_mac() does. Multicast addresses are rejected.
+    // The VM must be off
+    pub fn set_static_mac(id: &VmId, adapter_name: &str, mac: &str) -> Result<()> {
+        let mac = normalize_mac(mac)?;
+        let first_octet = u8::from_str_radix(&mac[..2], 16).map_err(|_| HypervError::new(format!("Invalid MAC address '{}'", mac)))?;
+        if first_octet & 1 != 0 {
+            return Err(HypervError::new(format!("'{}' is a multicast MAC address", mac)))
+        }
+        Self::set_adapter(id, adapter_name, &format!("-StaticMacAddress '{}'", mac))
+    }
+
+    // Lets Hyper-V assign the MAC from the host's pool again. The VM must be off
+    pub fn set_dynamic_mac(id: &VmId, adapter_name: &str) -> Result<()> {
+        Self::set_adapter(id, adapter_name, "-DynamicMacAddress")
+    }
+
+    // Lets the guest send traffic from MACs other than the adapter's own, as nested VMs and some
+    // routing appliances need
+    pub fn set_mac_spoofing(id: &VmId, adapter_name: &str, enabled: bool) -> Result<()> {
+        Self::set_adapter(id, adapter_name, &format!("-MacAddressSpoofing {}", on_off(enabled)))
+    }
+
+    // Drops DHCP server messages from the guest, so a rogue DHCP server in a VM cannot hand out addresses
+    pub fn set_dhcp_guard(id: &VmId, adapter_name: &str, enabled: bool) -> Result<()> {
+        Self::set_adapter(id, adapter_name, &format!("-DhcpGuard {}", on_off(enabled)))
+    }
+
+    // Drops router advertisements and redirects from the guest, so it cannot pose as a router
+    pub fn set_router_guard(id: &VmId, adapter_name: &str, enabled: bool) -> Result<()> {
+        Self::set_adapter(id, adapter_name, &format!("-RouterGuard {}", on_off(enabled)))
+    }
+
+    pub fn set_port_mirroring(id: &VmId, adapter_name: &str, mode: PortMirroring) -> Result<()> {
+        let mode = match mode {
+            PortMirroring::None => "None",
+            PortMirroring::Source => "Source",
+            PortMirroring::Destination => "Destination",
+        };
+        Self::set_adapter(id, adapter_name, &format!("-PortMirroring {}", mode))
+    }
+
+    // 1 to 100 asks for a virtual function for the adapter, with higher weights winning when the physical
+    // adapter runs out of them. 0 turns SR-IOV off. Only has an effect on switches created with SR-IOV
+    pub fn set_iov_weight(id: &VmId, adapter_name: &str, weight: u32) -> Result<()> {
+        if weight > MAX_IOV_WEIGHT {
+            return Err(HypervError::new(format!("Invalid IOV weight {}. Must be between 0 and {}", weight, MAX_IOV_WEIGHT)))
+        }
+        Self::set_adapter(id, adapter_name, &format!("-IovWeight {}", weight))
+    }
+
+    pub fn get_adapter_bandwidth(id: &VmId, adapter_name: &str) -> Result<Bandwidth> {
+        let command = format!(
+            "({}).BandwidthSetting | select-object -property MinimumBandwidthAbsolute,MaximumBandwidth | convertto-json",
+            adapter(id, adapter_name));
+        let raw: Option<RawBandwidth> = Self::spawn_and_deserialize(&command)?;
+        let from_bits = |bits: Option<u64>| bits.filter(|b| *b > 0).map(|b| b / 8);
+        Ok(raw.map_or_else(Bandwidth::default, |raw| Bandwidth {
+            minimum_bytes_per_sec: from_bits(raw.minimum),
+            maximum_bytes_per_sec: from_bits(raw.maximum),
+        }))
+    }
+
+    // A minimum only takes effect on switches created in absolute minimum bandwidth mode
+    pub fn set_adapter_bandwidth(id: &VmId, adapter_name: &str, bandwidth: &Bandwidth) -> Result<()> {
+        let to_bits = |bytes: Option<u64>| bytes.unwrap_or(0).checked_mul(8).ok_or_else(|| HypervError::new("Bandwidth is too large"));
+        let (minimum, maximum) = (to_bits(bandwidth.minimum_bytes_per_sec)?, to_bits(bandwidth.maximum_bytes_per_sec)?);
+        if maximum > 0 && minimum > maximum {
+            return Err(HypervError::new("Minimum bandwidth cannot exceed the maximum"))
+        }
+        Self::set_adapter(id, adapter_name, &format!("-MinimumBandwidthAbsolute {} -MaximumBandwidth {}", minimum, maximum))
+    }
+
+    fn set_adapter(id: &VmId, adapter_name: &str, params: &str) -> Result<()> {
+        let command = format!("{} | set-vmnetworkadapter {} -ErrorAction Stop", adapter(id, adapter_name), params);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled { "On" } else { "Off" }
+}
+
+// Pipeline yielding the named network adapter of the VM
+pub(crate) fn adapter(id: &VmId, adapter_name: &str) -> String {
+    format!("get-vm -Id '{}' -ErrorAction Stop | get-vmnetworkadapter -Name {} -ErrorAction Stop", id, ps_quote(adapter_name))
+}
+
+fn check_vlan_id(vlan: u16) -> Result<u16> {
+    if vlan == 0 || vlan > MAX_VLAN_ID {
+        return Err(HypervError::new(format!("Invalid VLAN id {}. Must be between 1 and {}", vlan, MAX_VLAN_ID)))
+    }
+    Ok(vlan)
+}
+
+// "1-100,200" and the like
+fn parse_vlan_list(list: &str) -> Result<Vec<RangeInclusive<u16>>> {
+    let invalid = || HypervError::new(format!("Invalid VLAN list '{}'", list));
+    list.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut bounds = part.splitn(2, '-');
+            let start = bounds.next().ok_or_else(invalid)?.trim().parse::<u16>().map_err(|_| invalid())?;
+            let end = match bounds.next() {
+                Some(end) => end.trim().parse::<u16>().map_err(|_| invalid())?,
+                None => start,
+            };
+            Ok(start..=end)
+        })
+        .collect()
+}
+
+fn format_vlan_list(ranges: &[RangeInclusive<u16>]) -> String {
+    ranges.iter()
+        .map(|r| if r.start() == r.end() { r.start().to_string() } else { format!("{}-{}", r.start(), r.end()) })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vlan_lists_round_trip() {
+        let ranges = parse_vlan_list("1-100, 200,300-301").unwrap();
+        assert_eq!(ranges, vec![1..=100, 200..=200, 300..=301]);
+        assert_eq!(format_vlan_list(&ranges), "1-100,200,300-301");
+        assert!(parse_vlan_list("").unwrap().is_empty());
+        assert!(parse_vlan_list("1-x").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{HypervError, Result};
+
+struct Step<'a> {
+    name: String,
+    run: Box<dyn FnOnce() -> Result<()> + 'a>,
+    rollback: Option<Box<dyn FnOnce() -> Result<()> + 'a>>,
+}
+
+// A sequence of operations that either all take effect or are undone. When a step fails, the
+// rollback actions of the steps that already completed are run in reverse order, so e.g. a VHD
+// created by an earlier step gets deleted instead of being left behind on the host
+pub struct Batch<'a> {
+    steps: Vec<Step<'a>>,
+}
+
+impl<'a> Batch<'a> {
+    pub fn new() -> Self {
+        Self { steps: Vec::new() }
+    }
+
+    // A step with nothing to undo, e.g. a pure validation or query
+    pub fn step<S: Into<String>, F: FnOnce() -> Result<()> + 'a>(mut self, name: S, run: F) -> Self {
+        self.steps.push(Step { name: name.into(), run: Box::new(run), rollback: None });
+        self
+    }
+
+    pub fn step_with_rollback<S, F, R>(mut self, name: S, run: F, rollback: R) -> Self
+        where S: Into<String>, F: FnOnce() -> Result<()> + 'a, R: FnOnce() -> Result<()> + 'a {
+        self.steps.push(Step { name: name.into(), run: Box::new(run), rollback: Some(Box::new(rollback)) });
+        self
+    }
+
+    pub fn run(self) -> Result<()> {
+        let mut completed = Vec::new();
+        for step in self.steps {
+            match (step.run)() {
+                Ok(()) => completed.push((step.name, step.rollback)),
+                Err(e) => {
+                    let mut failed_rollbacks = Vec::new();
+                    for (name, rollback) in completed.into_iter().rev() {
+                        if let Some(rollback) = rollback {
+                            if let Err(re) = rollback() {
+                                failed_rollbacks.push(format!("'{}': {}", name, re));
+                            }
+                        }
+                    }
+
+                    let msg = if failed_rollbacks.is_empty() {
+                        format!("Step '{}' failed: {}. Completed steps were rolled back", step.name, e)
+                    } else {
+                        format!("Step '{}' failed: {}. Rollback of these steps also failed: {}", step.name, e, failed_rollbacks.join("; "))
+                    };
+                    return Err(HypervError::new(msg))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Default for Batch<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
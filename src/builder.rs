@@ -0,0 +1,140 @@
+use crate::{Hyperv, HypervError, Result, Vm, VmProp, ps_quote};
+use crate::vhd::is_vhdx;
+use std::path::{Path, PathBuf};
+
+const MB: u64 = 1024 * 1024;
+
+// Composes a New-VM invocation, e.g.
+// VmBuilder::new("web").generation(2).memory_mb(4096).vhd_path("C:\\vms\\web.vhdx").switch("Default Switch").create()
+// At most one of vhd_path(), new_vhd() and no_vhd() may be used. Without any of them the VM gets no disk
+pub struct VmBuilder {
+    name: String,
+    generation: Option<u8>,
+    memory_mb: Option<u64>,
+    vhd_path: Option<PathBuf>,
+    new_vhd: Option<(PathBuf, u64)>,
+    no_vhd: bool,
+    switch: Option<String>,
+    path: Option<PathBuf>,
+}
+
+impl VmBuilder {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            generation: None,
+            memory_mb: None,
+            vhd_path: None,
+            new_vhd: None,
+            no_vhd: false,
+            switch: None,
+            path: None,
+        }
+    }
+
+    // 1 or 2. Hyper-V defaults to 1
+    pub fn generation(mut self, generation: u8) -> Self {
+        self.generation = Some(generation);
+        self
+    }
+
+    // Startup memory. Must be a multiple of 2 MB
+    pub fn memory_mb(mut self, memory_mb: u64) -> Self {
+        self.memory_mb = Some(memory_mb);
+        self
+    }
+
+    // Attaches an existing disk
+    pub fn vhd_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.vhd_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    // Creates a new dynamically expanding disk and attaches it
+    pub fn new_vhd<P: AsRef<Path>>(mut self, path: P, size_bytes: u64) -> Self {
+        self.new_vhd = Some((path.as_ref().to_owned(), size_bytes));
+        self
+    }
+
+    pub fn no_vhd(mut self) -> Self {
+        self.no_vhd = true;
+        self
+    }
+
+    pub fn switch<S: Into<String>>(mut self, switch: S) -> Self {
+        self.switch = Some(switch.into());
+        self
+    }
+
+    // Directory the VM's configuration is stored in. Hyper-V's default location is used otherwise
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn create(&self) -> Result<Vm> {
+        let command = format!(
+            "new-vm -Name {} {} -ErrorAction Stop | select-object -property {} | convertto-json",
+            ps_quote(&self.name),
+            self.params()?,
+            VmProp::projection_of(&VmProp::ALL));
+        let vm: Option<Vm> = Hyperv::spawn_mutation_and_deserialize(&command)?;
+        vm.ok_or_else(|| HypervError::new("New-VM did not return the created VM"))
+    }
+
+    fn params(&self) -> Result<String> {
+        let mut params = Vec::new();
+
+        if let Some(generation) = self.generation {
+            if generation != 1 && generation != 2 {
+                return Err(HypervError::new(format!("Invalid VM generation {}. Must be 1 or 2", generation)))
+            }
+            params.push(format!("-Generation {}", generation));
+        }
+
+        if let Some(memory_mb) = self.memory_mb {
+            if memory_mb == 0 || memory_mb % 2 != 0 {
+                return Err(HypervError::new(format!("Invalid memory size {} MB. Must be a non-zero multiple of 2 MB", memory_mb)))
+            }
+            params.push(format!("-MemoryStartupBytes {}", memory_mb * MB));
+        }
+
+        let disk_options = [self.vhd_path.is_some(), self.new_vhd.is_some(), self.no_vhd].iter().filter(|o| **o).count();
+        if disk_options > 1 {
+            return Err(HypervError::new("Only one of an existing VHD, a new VHD or no VHD can be chosen"))
+        }
+
+        if let Some(path) = &self.vhd_path {
+            self.check_disk_format(path)?;
+            params.push(format!("-VHDPath {}", ps_quote(path_str(path)?)));
+        }
+        if let Some((path, size_bytes)) = &self.new_vhd {
+            self.check_disk_format(path)?;
+            params.push(format!("-NewVHDPath {} -NewVHDSizeBytes {}", ps_quote(path_str(path)?), size_bytes));
+        }
+        if self.no_vhd {
+            params.push("-NoVHD".to_owned());
+        }
+
+        if let Some(switch) = &self.switch {
+            params.push(format!("-SwitchName {}", ps_quote(switch)));
+        }
+        if let Some(path) = &self.path {
+            params.push(format!("-Path {}", ps_quote(path_str(path)?)));
+        }
+
+        Ok(params.join(" "))
+    }
+
+    // Generation 2 VMs can only boot from VHDX files
+    fn check_disk_format(&self, path: &Path) -> Result<()> {
+        if !is_vhdx(path)? && self.generation == Some(2) {
+            return Err(HypervError::new("Generation 2 VMs require a VHDX disk"))
+        }
+        Ok(())
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| HypervError::new("Bad path"))
+}
@@ -0,0 +1,117 @@
+use crate::{Hyperv, IovSupport, NumaNode, Result, SchedulerStatus, VSwitch, Vm, VmProp};
+use crate::remote;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// Bumped by every mutating operation, whichever API it was issued through.
+// A cache entry filled under an older generation is treated as expired
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn note_mutation() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+fn generation() -> usize {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+struct Entry<T> {
+    value: T,
+    fetched_at: Instant,
+    generation: usize,
+}
+
+impl<T: Clone> Entry<T> {
+    fn get(&self, ttl: Duration) -> Option<T> {
+        if self.generation == generation() && self.fetched_at.elapsed() < ttl {
+            Some(self.value.clone())
+        } else {
+            None
+        }
+    }
+}
+
+// The host entries were fetched from, None being this one. Inventory queried inside HypervHost::run()
+// is only served back to callers targeting the same host
+type HostKey = Option<String>;
+
+fn current_host_key() -> HostKey {
+    remote::current_host().map(|h| h.computer_name().to_lowercase())
+}
+
+type Entries<K, T> = Mutex<HashMap<K, Entry<T>>>;
+
+// Memoizes VM, switch and host queries for a fixed time-to-live, separately for each host. Entries are
+// dropped early when any mutating operation is issued through this crate, whichever host it targets
+pub struct InventoryCache {
+    ttl: Duration,
+    vms: Entries<(HostKey, Vec<VmProp>), Vec<Vm>>,
+    switches: Entries<HostKey, Vec<VSwitch>>,
+    numa_topology: Entries<HostKey, Vec<NumaNode>>,
+    scheduler_type: Entries<HostKey, SchedulerStatus>,
+    iov_support: Entries<HostKey, IovSupport>,
+}
+
+impl InventoryCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            vms: Mutex::new(HashMap::new()),
+            switches: Mutex::new(HashMap::new()),
+            numa_topology: Mutex::new(HashMap::new()),
+            scheduler_type: Mutex::new(HashMap::new()),
+            iov_support: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get_vms(&self) -> Result<Vec<Vm>> {
+        self.get_vms_with(&VmProp::ALL)
+    }
+
+    pub fn get_vms_with(&self, props: &[VmProp]) -> Result<Vec<Vm>> {
+        let mut key = props.to_vec();
+        key.sort();
+        key.dedup();
+        self.cached(&self.vms, (current_host_key(), key), || Hyperv::get_vms_with(props))
+    }
+
+    pub fn get_switches(&self) -> Result<Vec<VSwitch>> {
+        self.cached(&self.switches, current_host_key(), VSwitch::list)
+    }
+
+    pub fn get_host_numa_topology(&self) -> Result<Vec<NumaNode>> {
+        self.cached(&self.numa_topology, current_host_key(), Hyperv::get_host_numa_topology)
+    }
+
+    pub fn get_scheduler_type(&self) -> Result<SchedulerStatus> {
+        self.cached(&self.scheduler_type, current_host_key(), Hyperv::get_scheduler_type)
+    }
+
+    pub fn get_iov_support(&self) -> Result<IovSupport> {
+        self.cached(&self.iov_support, current_host_key(), Hyperv::get_iov_support)
+    }
+
+    pub fn invalidate(&self) {
+        self.vms.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        self.switches.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        self.numa_topology.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        self.scheduler_type.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        self.iov_support.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    fn cached<K, T, F>(&self, entries: &Entries<K, T>, key: K, fetch: F) -> Result<T>
+        where K: Hash + Eq, T: Clone, F: FnOnce() -> Result<T> {
+        if let Some(value) = entries.lock().unwrap_or_else(|e| e.into_inner()).get(&key).and_then(|e| e.get(self.ttl)) {
+            return Ok(value)
+        }
+
+        let generation = generation();
+        let value = fetch()?;
+        entries.lock().unwrap_or_else(|e| e.into_inner())
+            .insert(key, Entry { value: value.clone(), fetched_at: Instant::now(), generation });
+        Ok(value)
+    }
+}
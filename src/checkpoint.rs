@@ -0,0 +1,160 @@
+use crate::{Hyperv, HypervError, Result, VmId, convert, ps_quote};
+use serde_derive::Deserialize;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+const SAFETY_CHECKPOINT_NAME: &str = "hyperv_rs safety checkpoint";
+
+const CHECKPOINT_PROJECTION: &str = "@{Name='Id';Expression={$_.Id.ToString()}},@{Name='VMId';Expression={$_.VMId.ToString()}},Name,\
+    @{Name='ParentId';Expression={ if ($_.ParentSnapshotId) { $_.ParentSnapshotId.ToString() } else { $null } }},\
+    @{Name='CreationTime';Expression={([DateTimeOffset]$_.CreationTime).ToUnixTimeMilliseconds()}}";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Checkpoint {
+    #[serde(rename = "Id")]
+    pub id: Uuid,
+    #[serde(rename = "VMId")]
+    pub vm_id: VmId,
+    #[serde(rename = "Name")]
+    pub name: String,
+    // None for the root of the tree
+    #[serde(rename = "ParentId", default)]
+    pub parent_id: Option<Uuid>,
+    #[serde(rename = "CreationTime", default, deserialize_with = "convert::deserialize_datetime")]
+    pub creation_time: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckpointNode {
+    pub checkpoint: Checkpoint,
+    pub children: Vec<CheckpointNode>,
+}
+
+impl Hyperv {
+    // Whether this is a standard or production checkpoint depends on the VM's checkpoint type setting
+    pub fn create_checkpoint(id: &VmId, name: &str) -> Result<Checkpoint> {
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | checkpoint-vm -SnapshotName {} -Passthru -ErrorAction Stop | select-object -property {} | convertto-json",
+            id,
+            ps_quote(name),
+            CHECKPOINT_PROJECTION);
+        let checkpoint: Option<Checkpoint> = Self::spawn_mutation_and_deserialize(&command)?;
+        checkpoint.ok_or_else(|| HypervError::new("Checkpoint-VM did not return the created checkpoint"))
+    }
+
+    // Oldest first
+    pub fn list_checkpoints(id: &VmId) -> Result<Vec<Checkpoint>> {
+        let command = format!(
+            "convertto-json -InputObject @(get-vm -Id '{}' -ErrorAction Stop | get-vmsnapshot | sort-object -Property CreationTime | select-object -property {})",
+            id,
+            CHECKPOINT_PROJECTION);
+        Self::spawn_and_deserialize_list(&command)
+    }
+
+    // The VM's checkpoints arranged by parent. Usually there is a single root, but checkpoints whose
+    // parent has gone missing become roots too. Siblings are ordered oldest first
+    pub fn get_checkpoint_tree(id: &VmId) -> Result<Vec<CheckpointNode>> {
+        Ok(build_tree(Self::list_checkpoints(id)?))
+    }
+
+    // The checkpoint the VM's current state ("Now") descends from, if any. Checkpoints created from
+    // here on become its children
+    pub fn get_current_checkpoint_id(id: &VmId) -> Result<Option<Uuid>> {
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop; if ($vm.ParentSnapshotId) {{ $vm.ParentSnapshotId.ToString() | convertto-json }}",
+            id);
+        Self::spawn_and_deserialize(&command)
+    }
+
+    // Reverts the VM to the checkpoint. Its current state is discarded
+    pub fn apply_checkpoint(checkpoint: &Checkpoint) -> Result<()> {
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | get-vmsnapshot | where-object {{ $_.Id -eq '{}' }} | restore-vmsnapshot -Confirm:$false -ErrorAction Stop",
+            checkpoint.vm_id,
+            checkpoint.id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // The checkpoint's disk changes are merged into its children, or into the VM itself if it has none.
+    // With include_children the whole subtree below the checkpoint is removed as well
+    pub fn remove_checkpoint(checkpoint: &Checkpoint, include_children: bool) -> Result<()> {
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | get-vmsnapshot | where-object {{ $_.Id -eq '{}' }} | remove-vmsnapshot {} -Confirm:$false -ErrorAction Stop",
+            checkpoint.vm_id,
+            checkpoint.id,
+            if include_children { "-IncludeAllChildSnapshots" } else { "" });
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Runs f with a checkpoint of the VM taken beforehand. The checkpoint is removed if f succeeds
+    // and applied (then removed) if it fails, so the VM ends up either fully reconfigured or untouched
+    pub fn with_safety_checkpoint<T, F: FnOnce() -> Result<T>>(id: &VmId, f: F) -> Result<T> {
+        let checkpoint = Self::create_checkpoint(id, SAFETY_CHECKPOINT_NAME)?;
+
+        match f() {
+            Ok(t) => {
+                Self::remove_checkpoint(&checkpoint, false)?;
+                Ok(t)
+            },
+            Err(e) => {
+                Self::apply_checkpoint(&checkpoint)
+                    .and_then(|_| Self::remove_checkpoint(&checkpoint, false))
+                    .map_err(|re| HypervError::new(format!("{}. Rolling back to safety checkpoint {} also failed: {}", e, checkpoint.id, re)))?;
+                Err(e)
+            },
+        }
+    }
+}
+
+// Expects the checkpoints oldest first and keeps that order among siblings
+fn build_tree(checkpoints: Vec<Checkpoint>) -> Vec<CheckpointNode> {
+    fn children_of(parent: Option<Uuid>, checkpoints: &[Checkpoint], is_root: &dyn Fn(&Checkpoint) -> bool) -> Vec<CheckpointNode> {
+        checkpoints.iter()
+            .filter(|c| match parent {
+                Some(parent) => c.parent_id == Some(parent),
+                None => is_root(c),
+            })
+            .map(|c| CheckpointNode { checkpoint: c.clone(), children: children_of(Some(c.id), checkpoints, is_root) })
+            .collect()
+    }
+
+    let is_root = |c: &Checkpoint| c.parent_id.is_none_or(|p| !checkpoints.iter().any(|other| other.id == p));
+    children_of(None, &checkpoints, &is_root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uuid(n: u32) -> Uuid {
+        Uuid::parse_str(&format!("00000000-0000-0000-0000-{:012}", n)).unwrap()
+    }
+
+    fn checkpoint(id: u32, parent_id: Option<u32>) -> Checkpoint {
+        Checkpoint {
+            id: uuid(id),
+            vm_id: VmId::from_uuid(uuid(0)),
+            name: id.to_string(),
+            parent_id: parent_id.map(uuid),
+            creation_time: None,
+        }
+    }
+
+    #[test]
+    fn builds_tree_from_parent_ids() {
+        let tree = build_tree(vec![checkpoint(1, None), checkpoint(2, Some(1)), checkpoint(3, Some(1)), checkpoint(4, Some(2))]);
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].checkpoint.id, uuid(1));
+        let children: Vec<_> = tree[0].children.iter().map(|n| n.checkpoint.id).collect();
+        assert_eq!(children, vec![uuid(2), uuid(3)]);
+        assert_eq!(tree[0].children[0].children[0].checkpoint.id, uuid(4));
+    }
+
+    #[test]
+    fn orphaned_checkpoints_become_roots() {
+        let tree = build_tree(vec![checkpoint(1, None), checkpoint(2, Some(9))]);
+        assert_eq!(tree.iter().map(|n| n.checkpoint.id).collect::<Vec<_>>(), vec![uuid(1), uuid(2)]);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{Credential, ErrorKind, Hyperv, HypervError, Result, ps_quote};
+use crate::credential::{CredentialScope, referenced_secrets};
+use crate::{error_record, remote};
+use std::sync::RwLock;
+
+// JEA endpoint that every command is routed through, if any
+static CONSTRAINED_ENDPOINT: RwLock<Option<String>> = RwLock::new(None);
+// Local account the PowerShell backend runs as, if not the current one
+static RUN_AS: RwLock<Option<Credential>> = RwLock::new(None);
+
+impl Hyperv {
+    // Routes all subsequent operations through Invoke-Command against the named JEA session configuration
+    // on this host, for environments where users may only manage Hyper-V through such an endpoint.
+    // Commands are then run without anything ConstrainedLanguage mode forbids, and operations that cannot
+    // work that way fail with ErrorKind::NotSupported. Pass None to go back to running commands directly
+    pub fn use_constrained_endpoint(configuration_name: Option<&str>) {
+        *CONSTRAINED_ENDPOINT.write().unwrap_or_else(|e| e.into_inner()) = configuration_name.map(|n| n.to_owned());
+    }
+
+    pub fn constrained_endpoint() -> Option<String> {
+        CONSTRAINED_ENDPOINT.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // Runs all subsequent operations in a PowerShell process started under the given local account,
+    // so a low privileged service can delegate Hyper-V management to an account in Hyper-V Administrators.
+    // Pass None to go back to running as the current account
+    pub fn run_as(credential: Option<Credential>) {
+        *RUN_AS.write().unwrap_or_else(|e| e.into_inner()) = credential;
+        crate::privileges::forget_privileges();
+    }
+
+    // For operations relying on language features ConstrainedLanguage mode blocks, like Add-Type or COM
+    pub(crate) fn require_full_language(operation: &str) -> Result<()> {
+        match Self::constrained_endpoint() {
+            Some(endpoint) => Err(HypervError::with_kind(ErrorKind::NotSupported, format!("{} is not available through the constrained endpoint '{}'", operation, endpoint))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "wmi")]
+pub(crate) fn runs_as_other_account() -> bool {
+    RUN_AS.read().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+// Wraps the command for the remote host, constrained endpoint and alternate account if any are in use.
+// The invariant culture preamble is only added without an endpoint since it calls .NET APIs
+// ConstrainedLanguage mode does not allow. The returned scopes must be kept alive until the command
+// has been spawned. A failing command reports its error record on stderr (see error_record)
+pub(crate) fn prepare_command(command: &str) -> (String, Vec<CredentialScope>) {
+    let mut scopes = Vec::new();
+    let host = remote::current_host();
+    let computer_name = host.as_ref().map(|h| h.computer_name()).unwrap_or("localhost");
+    let host_credential = host.as_ref().and_then(|h| h.credential()).map(|credential| {
+        let (scope, credential) = credential.expose();
+        scopes.push(scope);
+        credential
+    });
+
+    let command = match Hyperv::constrained_endpoint() {
+        Some(endpoint) => invoke_command(computer_name, Some(&endpoint), host_credential.as_deref(), command),
+        None => {
+            let command = format!("{}{}", crate::convert::INVARIANT_CULTURE_PREAMBLE, command);
+            match host {
+                Some(_) => invoke_command(computer_name, None, host_credential.as_deref(), &command),
+                None => command,
+            }
+        },
+    };
+
+    let command = error_record::wrap(&command);
+    match &*RUN_AS.read().unwrap_or_else(|e| e.into_inner()) {
+        Some(credential) => {
+            let (scope, credential) = credential.expose();
+            scopes.push(scope);
+            (run_as_command(&command, &credential), scopes)
+        },
+        None => (command, scopes),
+    }
+}
+
+// Credentials the command builds, e.g. for PowerShell Direct, read their passwords from environment
+// variables of this process. Those are handed to the other session as arguments, which WinRM encrypts
+fn invoke_command(computer_name: &str, configuration_name: Option<&str>, credential: Option<&str>, command: &str) -> String {
+    let secrets = referenced_secrets(command);
+    let (arguments, param) = if secrets.is_empty() {
+        (String::new(), "")
+    } else {
+        let entries = secrets.iter().map(|var| format!("'{0}' = $env:{0}", var)).collect::<Vec<_>>().join("; ");
+        (
+            format!("-ArgumentList @{{ {} }}", entries),
+            "param($secrets) foreach ($secret in $secrets.GetEnumerator()) { set-item -Path \"env:$($secret.Key)\" -Value $secret.Value };",
+        )
+    };
+    format!(
+        "invoke-command -ComputerName {} {} {} {} -ErrorAction Stop -ScriptBlock {{ {} {} }}",
+        ps_quote(computer_name),
+        configuration_name.map(|c| format!("-ConfigurationName {}", ps_quote(c))).unwrap_or_default(),
+        credential.map(|c| format!("-Credential {}", c)).unwrap_or_default(),
+        arguments,
+        param,
+        command)
+}
+
+// Start-Process opens the redirection files in this process and hands their handles to the child,
+// so the other account needs no access to the temp directory
+fn run_as_command(command: &str, credential: &str) -> String {
+    format!(
+        "$out = [IO.Path]::GetTempFileName(); $err = [IO.Path]::GetTempFileName();
+        try {{
+            $p = start-process powershell.exe -Credential {} -WorkingDirectory $env:SystemRoot -NoNewWindow -Wait -PassThru -RedirectStandardOutput $out -RedirectStandardError $err -ArgumentList '-NoProfile','-NonInteractive','-EncodedCommand','{}';
+            get-content -Path $out -Raw;
+            $stderr = get-content -Path $err -Raw;
+            if ($stderr) {{ [Console]::Error.Write($stderr) }}
+            $code = $p.ExitCode
+        }} finally {{
+            remove-item -Path $out,$err -ErrorAction SilentlyContinue
+        }}
+        exit $code",
+        credential,
+        encode_command(command))
+}
+
+// The form powershell.exe -EncodedCommand takes, which needs no quoting
+pub(crate) fn encode_command(command: &str) -> String {
+    let utf16: Vec<u8> = command.encode_utf16().flat_map(|u| u.to_le_bytes().to_vec()).collect();
+    base64(&utf16)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_partial_chunks() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
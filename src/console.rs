@@ -0,0 +1,25 @@
+use crate::{Hyperv, HypervError, Result, VmId};
+use crate::remote;
+use std::process::Command;
+
+const VMCONNECT_EXE: &str = "vmconnect.exe";
+
+impl Hyperv {
+    // Opens the Virtual Machine Connection window for the VM and returns without waiting for it to close.
+    // The VM is addressed by Id since names need not be unique. config is passed to vmconnect as its -C argument.
+    // Inside HypervHost::run() the console connects to that host. vmconnect then authenticates as the
+    // current account and asks for credentials itself if that is not enough
+    pub fn open_console(id: &VmId, config: Option<&str>) -> Result<()> {
+        let id = id.to_string();
+        let server = remote::current_host().map(|h| h.computer_name().to_owned()).unwrap_or_else(|| "localhost".to_owned());
+        let mut command = Command::new(VMCONNECT_EXE);
+        command.args([server.as_str(), "-G", &id]);
+        if let Some(config) = config {
+            command.args(["-C", config]);
+        }
+
+        command.spawn()
+            .map_err(|e| HypervError::new(format!("Failed to launch {}: {}", VMCONNECT_EXE, e)))?;
+        Ok(())
+    }
+}
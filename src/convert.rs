@@ -0,0 +1,144 @@
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+use serde_json::Value;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TICKS_PER_SEC: f64 = 10_000_000.0;
+
+// Spawned sessions run under the invariant culture so numbers and dates PowerShell formats as text
+// look the same on every host, whatever its regional settings
+pub(crate) const INVARIANT_CULTURE_PREAMBLE: &str =
+    "[System.Threading.Thread]::CurrentThread.CurrentCulture = [System.Globalization.CultureInfo]::InvariantCulture;
+    [System.Threading.Thread]::CurrentThread.CurrentUICulture = [System.Globalization.CultureInfo]::InvariantCulture;
+    ";
+
+// Accepts a DateTime in any of the forms it reaches us in: unix milliseconds (what our own scripts emit),
+// the "\/Date(ms)\/" form ConvertTo-Json uses in Windows PowerShell, or an ISO 8601 style UTC string
+pub(crate) fn deserialize_datetime<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<SystemTime>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(n)) => n.as_i64().map(from_unix_millis).ok_or_else(|| D::Error::custom(format!("Invalid timestamp {}", n))).map(Some),
+        Some(Value::String(s)) => parse_datetime(&s).map(Some).ok_or_else(|| D::Error::custom(format!("Invalid date '{}'", s))),
+        Some(v) => Err(D::Error::custom(format!("Expected a date but got {}", v))),
+    }
+}
+
+// Accepts a TimeSpan as a number of seconds (what our own scripts emit), the object ConvertTo-Json
+// makes of a TimeSpan, or its invariant "[d.]hh:mm:ss[.fffffff]" string form
+pub(crate) fn deserialize_timespan<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(n)) => n.as_f64().filter(|s| *s >= 0.0).map(Duration::from_secs_f64).ok_or_else(|| D::Error::custom(format!("Invalid duration {}", n))).map(Some),
+        Some(Value::Object(o)) => o.get("Ticks").and_then(Value::as_f64).filter(|t| *t >= 0.0)
+            .map(|t| Duration::from_secs_f64(t / TICKS_PER_SEC))
+            .ok_or_else(|| D::Error::custom("TimeSpan object has no valid Ticks"))
+            .map(Some),
+        Some(Value::String(s)) => parse_timespan(&s).map(Some).ok_or_else(|| D::Error::custom(format!("Invalid duration '{}'", s))),
+        Some(v) => Err(D::Error::custom(format!("Expected a duration but got {}", v))),
+    }
+}
+
+fn from_unix_millis(ms: i64) -> SystemTime {
+    if ms >= 0 {
+        UNIX_EPOCH + Duration::from_millis(ms as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_millis(ms.unsigned_abs())
+    }
+}
+
+fn parse_datetime(s: &str) -> Option<SystemTime> {
+    if s.starts_with("/Date(") && s.ends_with(")/") {
+        // The milliseconds are UTC. A trailing +hhmm offset only says which zone the value was in
+        let inner = &s[6..s.len() - 2];
+        let end = inner.char_indices().skip(1).find(|(_, c)| *c == '+' || *c == '-').map_or(inner.len(), |(i, _)| i);
+        return inner[..end].parse::<i64>().ok().map(from_unix_millis)
+    }
+
+    parse_iso8601_utc(s)
+}
+
+// "yyyy-MM-ddTHH:mm:ss[.fffffff][Z]". Values without a zone are taken to be UTC
+fn parse_iso8601_utc(s: &str) -> Option<SystemTime> {
+    let s = s.trim_end_matches('Z');
+    let (date, time) = (s.get(..10)?, s.get(11..)?);
+    if s.as_bytes().get(10) != Some(&b'T') {
+        return None
+    }
+
+    let mut date_parts = date.split('-').map(|p| p.parse::<i64>());
+    let (year, month, day) = (date_parts.next()?.ok()?, date_parts.next()?.ok()?, date_parts.next()?.ok()?);
+    let secs_of_day = parse_clock(time)?;
+
+    let days = days_from_civil(year, month, day);
+    let millis = days * 86_400_000 + (secs_of_day * 1000.0).round() as i64;
+    Some(from_unix_millis(millis))
+}
+
+fn parse_timespan(s: &str) -> Option<Duration> {
+    let (days, clock) = match s.find('.') {
+        Some(dot) if dot < s.find(':')? => (s[..dot].parse::<u64>().ok()?, &s[dot + 1..]),
+        _ => (0, s),
+    };
+    let secs = parse_clock(clock)?;
+    Some(Duration::from_secs(days * 86_400) + Duration::from_secs_f64(secs))
+}
+
+// "hh:mm:ss[.fffffff]" to seconds
+fn parse_clock(s: &str) -> Option<f64> {
+    let mut parts = s.split(':');
+    let hours = parts.next()?.parse::<u64>().ok()?;
+    let minutes = parts.next()?.parse::<u64>().ok()?;
+    let seconds = parts.next()?.parse::<f64>().ok()?;
+    if parts.next().is_some() || minutes >= 60 || !(0.0..60.0).contains(&seconds) {
+        return None
+    }
+    Some((hours * 3600 + minutes * 60) as f64 + seconds)
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(json: &str) -> Option<SystemTime> {
+        deserialize_datetime(&mut serde_json::Deserializer::from_str(json)).unwrap()
+    }
+
+    fn timespan(json: &str) -> Option<Duration> {
+        deserialize_timespan(&mut serde_json::Deserializer::from_str(json)).unwrap()
+    }
+
+    #[test]
+    fn parses_all_datetime_forms() {
+        let expected = UNIX_EPOCH + Duration::from_millis(1_554_812_345_000);
+        assert_eq!(datetime("1554812345000"), Some(expected));
+        assert_eq!(datetime("\"\\/Date(1554812345000)\\/\""), Some(expected));
+        assert_eq!(datetime("\"/Date(1554812345000+0530)/\""), Some(expected));
+        assert_eq!(datetime("\"2019-04-09T12:19:05Z\""), Some(expected));
+        assert_eq!(datetime("null"), None);
+    }
+
+    #[test]
+    fn parses_all_timespan_forms() {
+        assert_eq!(timespan("90"), Some(Duration::from_secs(90)));
+        assert_eq!(timespan("{ \"Ticks\": 900000000, \"TotalSeconds\": 90.0 }"), Some(Duration::from_secs(90)));
+        assert_eq!(timespan("\"00:01:30\""), Some(Duration::from_secs(90)));
+        assert_eq!(timespan("\"1.00:01:30.5000000\""), Some(Duration::from_millis(86_490_500)));
+        assert_eq!(timespan("null"), None);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(deserialize_datetime(&mut serde_json::Deserializer::from_str("\"09/04/2019\"")).is_err());
+        assert!(deserialize_timespan(&mut serde_json::Deserializer::from_str("\"1:2\"")).is_err());
+    }
+}
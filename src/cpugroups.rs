@@ -0,0 +1,127 @@
+use crate::{Hyperv, HypervError, Result, VmId};
+use uuid::Uuid;
+
+// cpugroups.exe drives the Host Compute Service CPU group APIs. It is not part of Windows and has to be
+// downloaded from Microsoft separately and put on the PATH of the host
+const CPUGROUPS_EXE: &str = "cpugroups.exe";
+
+// A CPU cap of 65536 means the group may use all of its processors
+pub const CPU_CAP_UNLIMITED: u32 = 65536;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuGroup {
+    pub id: Uuid,
+    pub processor_ids: Vec<u32>,
+    // Fraction of the group's processors, out of CPU_CAP_UNLIMITED, that its VMs may use
+    pub cpu_cap: u32,
+}
+
+impl Hyperv {
+    pub fn list_cpu_groups() -> Result<Vec<CpuGroup>> {
+        let command = format!(
+            "& {} GetGroups | out-string -Width 4096;
+            if ($LASTEXITCODE -ne 0) {{ throw 'cpugroups.exe failed to list CPU groups' }}",
+            CPUGROUPS_EXE);
+        parse_groups(&Self::spawn_and_read(&command)?)
+    }
+
+    pub fn create_cpu_group(processor_ids: &[u32]) -> Result<CpuGroup> {
+        if processor_ids.is_empty() {
+            return Err(HypervError::new("A CPU group needs at least one processor"))
+        }
+
+        let id = Uuid::new_v4();
+        let lps = processor_ids.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(",");
+        let command = format!(
+            "& {} CreateGroup /GroupId:{} /GroupAffinity:{};
+            if ($LASTEXITCODE -ne 0) {{ throw 'cpugroups.exe failed to create the CPU group' }}",
+            CPUGROUPS_EXE,
+            id,
+            lps);
+        Self::spawn_mutation(&command)?;
+        Ok(CpuGroup { id, processor_ids: processor_ids.to_vec(), cpu_cap: CPU_CAP_UNLIMITED })
+    }
+
+    pub fn set_cpu_group_cap(group_id: &Uuid, cpu_cap: u32) -> Result<()> {
+        if cpu_cap == 0 || cpu_cap > CPU_CAP_UNLIMITED {
+            return Err(HypervError::new(format!("Invalid CPU cap {}. Must be between 1 and {}", cpu_cap, CPU_CAP_UNLIMITED)))
+        }
+
+        let command = format!(
+            "& {} SetGroupProperty /GroupId:{} /CpuCap:{};
+            if ($LASTEXITCODE -ne 0) {{ throw 'cpugroups.exe failed to set the CPU cap' }}",
+            CPUGROUPS_EXE,
+            group_id,
+            cpu_cap);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // The VM must be off
+    pub fn bind_vm_to_cpu_group(id: &VmId, group_id: &Uuid) -> Result<()> {
+        let command = format!(
+            "& {} SetVmGroup /VmGuid:{} /GroupId:{};
+            if ($LASTEXITCODE -ne 0) {{ throw 'cpugroups.exe failed to bind the VM to the CPU group' }}",
+            CPUGROUPS_EXE,
+            id,
+            group_id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Fails if any VM is still bound to the group
+    pub fn delete_cpu_group(group_id: &Uuid) -> Result<()> {
+        let command = format!(
+            "& {} DeleteGroup /GroupId:{};
+            if ($LASTEXITCODE -ne 0) {{ throw 'cpugroups.exe failed to delete the CPU group' }}",
+            CPUGROUPS_EXE,
+            group_id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+// GetGroups prints a table with a "CpuGroupId CpuCap LpIndexes" header and a line of dashes, then one row
+// per group. LpIndexes is a comma separated list
+fn parse_groups(output: &str) -> Result<Vec<CpuGroup>> {
+    let mut groups = Vec::new();
+    for line in output.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("CpuGroupId") || line.starts_with('-') {
+            continue
+        }
+        let invalid = || HypervError::new(format!("Unexpected cpugroups.exe output line '{}'", line));
+        let mut columns = line.split_whitespace();
+        let id = columns.next().and_then(|c| Uuid::parse_str(c).ok()).ok_or_else(invalid)?;
+        let cpu_cap = columns.next().and_then(|c| c.parse::<u32>().ok()).ok_or_else(invalid)?;
+        let processor_ids = columns.collect::<String>()
+            .split(',')
+            .filter(|p| !p.is_empty())
+            .map(|p| p.parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>>>()?;
+        groups.push(CpuGroup { id, processor_ids, cpu_cap });
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_get_groups_table() {
+        let output = "\r\n\
+            CpuGroupId                          CpuCap LpIndexes\r\n\
+            ------------------------------------ ------ ---------\r\n\
+            36AB08CB-3A76-4B38-992E-000000000002  32768 0,1,16,17\r\n\
+            36AB08CB-3A76-4B38-992E-000000000003  65536 2\r\n\
+            \r\n";
+        let groups = parse_groups(output).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].id, Uuid::parse_str("36ab08cb-3a76-4b38-992e-000000000002").unwrap());
+        assert_eq!(groups[0].cpu_cap, 32768);
+        assert_eq!(groups[0].processor_ids, vec![0, 1, 16, 17]);
+        assert_eq!(groups[1].processor_ids, vec![2]);
+        assert!(parse_groups("garbage 1 2").is_err());
+        assert!(parse_groups("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use std::env;
+use std::fmt;
+use uuid::Uuid;
+
+const SECRET_VAR_PREFIX: &str = "HYPERV_RS_SECRET_";
+// Length of the simple form of the Uuid that makes each variable name unique
+const SECRET_VAR_SUFFIX_LEN: usize = 32;
+
+// Username and password for operations that authenticate against a guest or another host
+#[derive(Clone)]
+pub struct Credential {
+    username: String,
+    password: String,
+}
+
+impl Credential {
+    pub fn new<U: Into<String>, P: Into<String>>(username: U, password: P) -> Self {
+        Self { username: username.into(), password: password.into() }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    // Hands the password to the next PowerShell process through an environment variable so that it
+    // never appears in the command line. Returns the PowerShell expression that builds the PSCredential.
+    // The variable is removed from this process when the returned scope is dropped, so keep it alive
+    // until the command has been spawned
+    pub(crate) fn expose(&self) -> (CredentialScope, String) {
+        let var = format!("{}{}", SECRET_VAR_PREFIX, Uuid::new_v4().to_simple());
+        env::set_var(&var, &self.password);
+        let expr = format!(
+            "(new-object System.Management.Automation.PSCredential({}, (convertto-securestring $env:{} -AsPlainText -Force)))",
+            crate::ps_quote(&self.username),
+            var);
+        (CredentialScope { var }, expr)
+    }
+}
+
+// Never print the password
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credential").field("username", &self.username).field("password", &"<hidden>").finish()
+    }
+}
+
+// Names of the variables expose() created that the command reads
+pub(crate) fn referenced_secrets(command: &str) -> Vec<String> {
+    let marker = format!("$env:{}", SECRET_VAR_PREFIX);
+    let mut vars: Vec<String> = command.match_indices(&marker)
+        .filter_map(|(i, _)| command.get(i + 5..i + marker.len() + SECRET_VAR_SUFFIX_LEN))
+        .filter(|var| var[SECRET_VAR_PREFIX.len()..].chars().all(|c| c.is_ascii_hexdigit()))
+        .map(|var| var.to_owned())
+        .collect();
+    vars.sort();
+    vars.dedup();
+    vars
+}
+
+pub(crate) struct CredentialScope {
+    var: String,
+}
+
+impl Drop for CredentialScope {
+    fn drop(&mut self) {
+        env::remove_var(&self.var);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_referenced_secrets() {
+        let credential = Credential::new("admin", "secret");
+        let (_scope, expr) = credential.expose();
+        let command = format!("invoke-command -VMId 'x' -Credential {0}; invoke-command -VMId 'y' -Credential {0}", expr);
+        let secrets = referenced_secrets(&command);
+        assert_eq!(secrets.len(), 1);
+        assert!(expr.contains(&format!("$env:{}", secrets[0])));
+        assert!(referenced_secrets("$env:HYPERV_RS_SECRET_short").is_empty());
+    }
+}
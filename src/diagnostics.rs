@@ -0,0 +1,192 @@
+use crate::{Credential, ErrorKind, Hyperv, HypervError, Result, VmId, VmProp, VmState, ps_quote};
+use serde_derive::Deserialize;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const WORKER_LOG: &str = "Microsoft-Windows-Hyper-V-Worker-Admin";
+const BUGCHECK_EVENT_ID: u32 = 18590;
+const TRIPLE_FAULT_EVENT_ID: u32 = 18560;
+const DUMP_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestCrashKind {
+    Bugcheck,
+    TripleFault,
+}
+
+#[derive(Debug, Clone)]
+pub struct GuestCrash {
+    pub kind: GuestCrashKind,
+    pub timestamp: SystemTime,
+    // The bugcheck code reported by the guest. Triple faults have none
+    pub error_code: Option<u64>,
+    pub message: String,
+    // State of the VM at the time of the query, e.g. Off or Paused depending on the VM's critical error action
+    pub vm_state: VmState,
+}
+
+// Where collect_guest_dump() leaves the dump once the guest has written it. The paths are the file the
+// dump is copied to on the host
+#[derive(Debug, Clone)]
+pub enum DumpRetrieval {
+    // The dump stays at %SystemRoot%\MEMORY.DMP inside the guest
+    LeaveInGuest,
+    // The VM is shut down and its boot disk mounted on the host to copy the dump out
+    MountVhd(PathBuf),
+    // The dump is copied out of the running guest over PowerShell Direct, which needs a Windows 10 /
+    // Server 2016 or later guest and an account in it
+    PowerShellDirect { credential: Credential, dest: PathBuf },
+}
+
+#[derive(Deserialize)]
+struct RawGuestCrash {
+    #[serde(rename = "EventId")]
+    event_id: u32,
+    #[serde(rename = "TimeCreated")]
+    time_created: u64,
+    #[serde(rename = "ErrorCode")]
+    error_code: Option<String>,
+    #[serde(rename = "Message")]
+    message: String,
+    #[serde(rename = "VmState")]
+    vm_state: VmState,
+}
+
+impl Hyperv {
+    pub fn get_last_guest_crash(id: &VmId) -> Result<Option<GuestCrash>> {
+        let command = format!(
+            "$vm = get-vm -Id '{id}' -ErrorAction Stop;
+            $event = Get-WinEvent -FilterHashtable @{{ LogName = '{log}'; Id = {bugcheck},{triple_fault} }} -ErrorAction SilentlyContinue |
+                Where-Object {{ $_.Message -like '*{id}*' }} | Select-Object -First 1;
+            if ($event) {{
+                $code = $null;
+                if ($event.Id -eq {bugcheck} -and $event.Message -match '0x[0-9a-fA-F]+') {{ $code = $matches[0] }}
+                [pscustomobject]@{{
+                    EventId = $event.Id;
+                    TimeCreated = ([DateTimeOffset]$event.TimeCreated).ToUnixTimeMilliseconds();
+                    ErrorCode = $code;
+                    Message = $event.Message;
+                    VmState = $vm.State.ToString()
+                }} | convertto-json
+            }}",
+            id = id,
+            log = WORKER_LOG,
+            bugcheck = BUGCHECK_EVENT_ID,
+            triple_fault = TRIPLE_FAULT_EVENT_ID);
+
+        let raw: Option<RawGuestCrash> = Self::spawn_and_deserialize(&command)?;
+        raw.map(GuestCrash::from_raw).transpose()
+    }
+
+    // Crashes the guest with an NMI, waits for it to bugcheck and come back up (Windows moves the dump
+    // out of the page file into MEMORY.DMP during the next boot) and optionally copies the dump out.
+    // The guest must be configured to write a dump on NMI and to restart after a bugcheck. Fails with
+    // ErrorKind::InvalidState if the VM is turned off, saved or paused instead, since it then never boots
+    // to write the dump. timeout applies to each wait separately
+    pub fn collect_guest_dump(id: &VmId, retrieval: &DumpRetrieval, timeout: Duration) -> Result<GuestCrash> {
+        let injected_at = SystemTime::now();
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | debug-vm -InjectNonMaskableInterrupt -Force -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+
+        let crash = Self::poll(timeout, "guest to bugcheck", || {
+            Ok(Self::get_last_guest_crash(id)?.filter(|c| c.timestamp >= injected_at))
+        })?;
+
+        Self::poll(timeout, "guest to boot after bugcheck", || {
+            if let Some(state) = Self::get_vm_with(id, &[VmProp::State])?.state {
+                if let VmState::Off | VmState::Saved | VmState::Paused = state {
+                    return Err(HypervError::with_kind(
+                        ErrorKind::InvalidState(state.clone()),
+                        format!("VM {} is {:?} after the bugcheck, so the guest will not boot to write the dump. Set its guest to restart after a bugcheck", id, state)))
+                }
+            }
+            Ok(if Self::heartbeat(id)?.is_ok() { Some(()) } else { None })
+        })?;
+
+        match retrieval {
+            DumpRetrieval::LeaveInGuest => {},
+            DumpRetrieval::MountVhd(dest) => {
+                let dest = dest.to_str().ok_or_else(|| HypervError::new("Bad path"))?;
+                // Partitions of a mounted disk only get drive letters if they had one when it was last mounted
+                let command = format!(
+                    "$vm = get-vm -Id '{}' -ErrorAction Stop;
+                    $vm | stop-vm -Force -ErrorAction Stop;
+                    $vhd = ($vm | get-vmharddiskdrive | select-object -First 1).Path;
+                    if (-not $vhd) {{ throw 'VM has no hard disk' }}
+                    $disk = mount-vhd -Path $vhd -ReadOnly -Passthru -ErrorAction Stop | get-disk;
+                    try {{
+                        $disk | get-partition | where-object {{ -not $_.DriveLetter -and $_.Type -eq 'Basic' }} |
+                            add-partitionaccesspath -AssignDriveLetter -ErrorAction SilentlyContinue;
+                        $dump = $disk | get-partition | where-object {{ $_.DriveLetter }} |
+                            foreach-object {{ \"$($_.DriveLetter):\\Windows\\MEMORY.DMP\" }} | where-object {{ test-path $_ }} | select-object -First 1;
+                        if (-not $dump) {{ throw 'No MEMORY.DMP found on the VM boot disk' }}
+                        copy-item -Path $dump -Destination {} -ErrorAction Stop
+                    }} finally {{
+                        dismount-vhd -Path $vhd
+                    }}",
+                    id,
+                    ps_quote(dest));
+                Self::spawn_mutation(&command)?;
+            },
+            DumpRetrieval::PowerShellDirect { credential, dest } => {
+                let dest = dest.to_str().ok_or_else(|| HypervError::new("Bad path"))?;
+                let (_scope, credential) = credential.expose();
+                let command = format!(
+                    "$session = new-pssession -VMId '{}' -Credential {} -ErrorAction Stop;
+                    try {{
+                        $dump = invoke-command -Session $session -ErrorAction Stop -ScriptBlock {{ join-path $env:SystemRoot 'MEMORY.DMP' }};
+                        copy-item -FromSession $session -Path $dump -Destination {} -ErrorAction Stop
+                    }} finally {{
+                        remove-pssession -Session $session
+                    }}",
+                    id,
+                    credential,
+                    ps_quote(dest));
+                Self::spawn_and_read(&command)?;
+            },
+        }
+
+        Ok(crash)
+    }
+
+    fn poll<T, F: FnMut() -> Result<Option<T>>>(timeout: Duration, waiting_for: &str, mut f: F) -> Result<T> {
+        let start = Instant::now();
+        loop {
+            if let Some(t) = f()? {
+                return Ok(t)
+            }
+            if start.elapsed() >= timeout {
+                return Err(HypervError::new(format!("Timed out after {:?} waiting for {}", timeout, waiting_for)))
+            }
+            thread::sleep(DUMP_POLL_INTERVAL);
+        }
+    }
+}
+
+impl GuestCrash {
+    fn from_raw(raw: RawGuestCrash) -> Result<Self> {
+        let kind = match raw.event_id {
+            BUGCHECK_EVENT_ID => GuestCrashKind::Bugcheck,
+            TRIPLE_FAULT_EVENT_ID => GuestCrashKind::TripleFault,
+            id => return Err(HypervError::new(format!("Unexpected Hyper-V worker event id {}", id))),
+        };
+
+        let error_code = match raw.error_code {
+            Some(code) => {
+                let code = u64::from_str_radix(code.trim_start_matches("0x"), 16)
+                    .map_err(|e| HypervError::new(format!("Failed to parse guest error code '{}': {}", code, e)))?;
+                Some(code)
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            kind,
+            timestamp: UNIX_EPOCH + Duration::from_millis(raw.time_created),
+            error_code,
+            message: raw.message,
+            vm_state: raw.vm_state,
+        })
+    }
+}
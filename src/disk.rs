@@ -0,0 +1,245 @@
+use crate::{Credential, ErrorKind, Hyperv, HypervError, Result, VmId, VmProp, VmState, ps_quote};
+use serde_derive::Deserialize;
+use std::path::{Path, PathBuf};
+
+const SCSI_LOCATIONS_PER_CONTROLLER: u32 = 64;
+const MAX_SCSI_CONTROLLERS: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerType {
+    Ide,
+    Scsi,
+}
+
+impl ControllerType {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ControllerType::Ide => "IDE",
+            ControllerType::Scsi => "SCSI",
+        }
+    }
+
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        match s {
+            "IDE" => Ok(ControllerType::Ide),
+            "SCSI" => Ok(ControllerType::Scsi),
+            s => Err(HypervError::new(format!("Unexpected controller type '{}'", s))),
+        }
+    }
+}
+
+// Where a drive is attached on a VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSlot {
+    pub controller_type: ControllerType,
+    pub controller_number: u32,
+    pub location: u32,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RawDiskSlot {
+    #[serde(rename = "ControllerType")]
+    controller_type: String,
+    #[serde(rename = "ControllerNumber")]
+    controller_number: u32,
+    #[serde(rename = "ControllerLocation")]
+    location: u32,
+}
+
+impl RawDiskSlot {
+    pub(crate) fn into_slot(self) -> Result<DiskSlot> {
+        Ok(DiskSlot {
+            controller_type: ControllerType::parse(&self.controller_type)?,
+            controller_number: self.controller_number,
+            location: self.location,
+        })
+    }
+}
+
+pub(crate) const SLOT_PROJECTION: &str = "@{Name='ControllerType';Expression={$_.ControllerType.ToString()}},ControllerNumber,ControllerLocation";
+
+// A hard disk drive attached to a VM. Path is None for pass-through physical disks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HardDisk {
+    pub slot: DiskSlot,
+    pub path: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RawHardDisk {
+    #[serde(flatten)]
+    slot: RawDiskSlot,
+    #[serde(rename = "Path")]
+    path: Option<String>,
+}
+
+impl RawHardDisk {
+    pub(crate) fn into_hard_disk(self) -> Result<HardDisk> {
+        Ok(HardDisk { slot: self.slot.into_slot()?, path: self.path })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScsiController {
+    pub number: u32,
+    pub drives: Vec<ControllerDrive>,
+}
+
+// A hard disk or DVD drive on a controller. Path is None for empty DVD drives and pass-through disks
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ControllerDrive {
+    #[serde(rename = "Location")]
+    pub location: u32,
+    #[serde(rename = "Path")]
+    pub path: Option<PathBuf>,
+    #[serde(rename = "Dvd")]
+    pub dvd: bool,
+}
+
+#[derive(Deserialize)]
+struct RawScsiController {
+    #[serde(rename = "Number")]
+    number: u32,
+    #[serde(rename = "Drives")]
+    drives: Vec<ControllerDrive>,
+}
+
+pub(crate) const HARD_DISK_PROJECTION: &str = "@{Name='ControllerType';Expression={$_.ControllerType.ToString()}},ControllerNumber,ControllerLocation,Path";
+
+impl Hyperv {
+    // Attaches the disk at the given slot, or the first free SCSI slot if none is given. SCSI disks can be
+    // attached to running VMs; IDE disks only while the VM is off. If a credential is given and the VM is
+    // running, the guest is asked over PowerShell Direct to rescan its disks so the new disk shows up
+    // straight away. The disk is attached whether or not the rescan works, so a failed rescan is not
+    // reported as an error. The guest finds the disk on its next rescan, or rescan_guest_disks() can retry
+    pub fn add_hard_disk<P: AsRef<Path>>(id: &VmId, path: P, slot: Option<&DiskSlot>, rescan: Option<&Credential>) -> Result<DiskSlot> {
+        let path = path.as_ref().to_str().ok_or_else(|| HypervError::new("Bad path"))?;
+        let running = Self::check_disk_hot_plug(id, slot.map_or(ControllerType::Scsi, |s| s.controller_type))?;
+
+        let slot_params = match slot {
+            Some(slot) => format!("-ControllerType {} -ControllerNumber {} -ControllerLocation {}", slot.controller_type.as_str(), slot.controller_number, slot.location),
+            None => "-ControllerType SCSI".to_owned(),
+        };
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | add-vmharddiskdrive {} -Path {} -Passthru -ErrorAction Stop | select-object -property {} | convertto-json",
+            id,
+            slot_params,
+            ps_quote(path),
+            SLOT_PROJECTION);
+        let raw: Option<RawDiskSlot> = Self::spawn_mutation_and_deserialize(&command)?;
+        let slot = raw.ok_or_else(|| HypervError::new("Add-VMHardDiskDrive did not return the attached drive"))?.into_slot()?;
+
+        if let (true, Some(credential)) = (running, rescan) {
+            let _ = Self::rescan_guest_disks(id, credential);
+        }
+
+        Ok(slot)
+    }
+
+    pub fn list_disks(id: &VmId) -> Result<Vec<HardDisk>> {
+        let command = format!(
+            "convertto-json -InputObject @(get-vm -Id '{}' -ErrorAction Stop | get-vmharddiskdrive | select-object -property {})",
+            id,
+            HARD_DISK_PROJECTION);
+        let raw: Vec<RawHardDisk> = Self::spawn_and_deserialize_list(&command)?;
+        raw.into_iter().map(RawHardDisk::into_hard_disk).collect()
+    }
+
+    pub fn remove_hard_disk(id: &VmId, slot: &DiskSlot) -> Result<()> {
+        Self::check_disk_hot_plug(id, slot.controller_type)?;
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | get-vmharddiskdrive -ControllerType {} -ControllerNumber {} -ControllerLocation {} -ErrorAction Stop | remove-vmharddiskdrive -ErrorAction Stop",
+            id,
+            slot.controller_type.as_str(),
+            slot.controller_number,
+            slot.location);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    pub fn list_scsi_controllers(id: &VmId) -> Result<Vec<ScsiController>> {
+        let command = format!(
+            "convertto-json -Depth 3 -InputObject @(get-vm -Id '{}' -ErrorAction Stop | get-vmscsicontroller | foreach-object {{
+                [pscustomobject]@{{
+                    Number = $_.ControllerNumber;
+                    Drives = @($_.Drives | foreach-object {{
+                        [pscustomobject]@{{ Location = $_.ControllerLocation; Path = $_.Path; Dvd = $_.GetType().Name -eq 'DvdDrive' }}
+                    }})
+                }}
+            }})",
+            id);
+        let raw: Vec<RawScsiController> = Self::spawn_and_deserialize_list(&command)?;
+        Ok(raw.into_iter().map(|c| ScsiController { number: c.number, drives: c.drives }).collect())
+    }
+
+    // A VM can have up to 4 SCSI controllers. It must be off to add one. Returns the new controller's number
+    pub fn add_scsi_controller(id: &VmId) -> Result<u32> {
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop;
+            if (@($vm | get-vmscsicontroller).Count -ge {}) {{ throw 'The VM already has the maximum number of SCSI controllers' }}
+            $vm | add-vmscsicontroller -ErrorAction Stop;
+            (@($vm | get-vmscsicontroller) | measure-object -Property ControllerNumber -Maximum).Maximum | convertto-json",
+            id,
+            MAX_SCSI_CONTROLLERS);
+        let number: Option<u32> = Self::spawn_mutation_and_deserialize(&command)?;
+        number.ok_or_else(|| HypervError::new("Add-VMScsiController did not report the added controller"))
+    }
+
+    // The slot the next disk should be attached at, or None if every SCSI controller is full,
+    // in which case add_scsi_controller() makes room
+    pub fn find_free_scsi_slot(id: &VmId) -> Result<Option<DiskSlot>> {
+        Ok(first_free_scsi_slot(&Self::list_scsi_controllers(id)?))
+    }
+
+    pub fn rescan_guest_disks(id: &VmId, credential: &Credential) -> Result<()> {
+        let (_scope, credential) = credential.expose();
+        let command = format!(
+            "invoke-command -VMId '{}' -Credential {} -ScriptBlock {{ update-hoststoragecache }} -ErrorAction Stop",
+            id,
+            credential);
+        Self::spawn_and_wait(&command)?;
+        Ok(())
+    }
+
+    // Returns whether the VM is running. A saved or paused VM is not, and its guest cannot be asked to rescan
+    fn check_disk_hot_plug(id: &VmId, controller_type: ControllerType) -> Result<bool> {
+        let vm = Self::get_vm_with(id, &[VmProp::State])?;
+        if vm.state != Some(VmState::Off) && controller_type == ControllerType::Ide {
+            return Err(HypervError::with_kind(ErrorKind::NotSupported, "IDE disks can only be attached or detached while the VM is off. Use a SCSI controller instead"))
+        }
+        Ok(vm.state == Some(VmState::Running))
+    }
+}
+
+fn first_free_scsi_slot(controllers: &[ScsiController]) -> Option<DiskSlot> {
+    let mut controllers: Vec<&ScsiController> = controllers.iter().collect();
+    controllers.sort_by_key(|c| c.number);
+    controllers.iter().find_map(|c| {
+        (0..SCSI_LOCATIONS_PER_CONTROLLER)
+            .find(|location| !c.drives.iter().any(|d| d.location == *location))
+            .map(|location| DiskSlot { controller_type: ControllerType::Scsi, controller_number: c.number, location })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller(number: u32, used: &[u32]) -> ScsiController {
+        ScsiController { number, drives: used.iter().map(|l| ControllerDrive { location: *l, path: None, dvd: false }).collect() }
+    }
+
+    #[test]
+    fn finds_first_gap_on_lowest_controller() {
+        let slot = first_free_scsi_slot(&[controller(1, &[]), controller(0, &[0, 1, 3])]).unwrap();
+        assert_eq!((slot.controller_number, slot.location), (0, 2));
+    }
+
+    #[test]
+    fn moves_on_when_controller_is_full() {
+        let full: Vec<u32> = (0..SCSI_LOCATIONS_PER_CONTROLLER).collect();
+        let slot = first_free_scsi_slot(&[controller(0, &full), controller(1, &[0])]).unwrap();
+        assert_eq!((slot.controller_number, slot.location), (1, 1));
+        assert!(first_free_scsi_slot(&[controller(0, &full)]).is_none());
+    }
+}
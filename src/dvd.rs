@@ -0,0 +1,95 @@
+use crate::{DiskSlot, Hyperv, HypervError, Result, VmId, ps_quote};
+use crate::disk::{RawDiskSlot, SLOT_PROJECTION};
+use serde_derive::Deserialize;
+use std::path::{Path, PathBuf};
+
+// Iso is None when the drive is empty or passes through a physical drive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DvdDrive {
+    pub slot: DiskSlot,
+    pub iso: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct RawDvdDrive {
+    #[serde(flatten)]
+    slot: RawDiskSlot,
+    #[serde(rename = "Path")]
+    path: Option<String>,
+}
+
+impl RawDvdDrive {
+    fn into_drive(self) -> Result<DvdDrive> {
+        Ok(DvdDrive { slot: self.slot.into_slot()?, iso: self.path.filter(|p| !p.is_empty()).map(PathBuf::from) })
+    }
+}
+
+impl Hyperv {
+    // Hyper-V picks the first free slot: on the IDE controllers for Generation 1 VMs, which must then
+    // be off, or on the SCSI controllers for Generation 2 VMs
+    pub fn add_dvd_drive(id: &VmId, iso: Option<&Path>) -> Result<DvdDrive> {
+        let path_param = match iso {
+            Some(iso) => format!("-Path {}", ps_quote(iso_str(iso)?)),
+            None => "".to_owned(),
+        };
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | add-vmdvddrive {} -Passthru -ErrorAction Stop | select-object -property {},Path | convertto-json",
+            id,
+            path_param,
+            SLOT_PROJECTION);
+        let raw: Option<RawDvdDrive> = Self::spawn_mutation_and_deserialize(&command)?;
+        raw.ok_or_else(|| HypervError::new("Add-VMDvdDrive did not return the added drive"))?.into_drive()
+    }
+
+    pub fn list_dvd_drives(id: &VmId) -> Result<Vec<DvdDrive>> {
+        let command = format!(
+            "convertto-json -InputObject @(get-vm -Id '{}' -ErrorAction Stop | get-vmdvddrive | select-object -property {},Path)",
+            id,
+            SLOT_PROJECTION);
+        let raw: Vec<RawDvdDrive> = Self::spawn_and_deserialize_list(&command)?;
+        raw.into_iter().map(RawDvdDrive::into_drive).collect()
+    }
+
+    // Replaces whatever is in the drive. Works while the VM is running
+    pub fn insert_iso<P: AsRef<Path>>(id: &VmId, slot: &DiskSlot, iso: P) -> Result<()> {
+        Self::set_dvd_media(id, slot, &ps_quote(iso_str(iso.as_ref())?))
+    }
+
+    pub fn eject_iso(id: &VmId, slot: &DiskSlot) -> Result<()> {
+        Self::set_dvd_media(id, slot, "$null")
+    }
+
+    pub fn remove_dvd_drive(id: &VmId, slot: &DiskSlot) -> Result<()> {
+        let command = format!(
+            "{} | remove-vmdvddrive -ErrorAction Stop",
+            dvd_drive_at(id, slot));
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    fn set_dvd_media(id: &VmId, slot: &DiskSlot, path_expr: &str) -> Result<()> {
+        let command = format!(
+            "{} | set-vmdvddrive -Path {} -ErrorAction Stop",
+            dvd_drive_at(id, slot),
+            path_expr);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+fn dvd_drive_at(id: &VmId, slot: &DiskSlot) -> String {
+    format!(
+        "get-vm -Id '{}' -ErrorAction Stop | get-vmdvddrive -ControllerNumber {} -ControllerLocation {} -ErrorAction Stop |
+            where-object {{ $_.ControllerType.ToString() -eq '{}' }}",
+        id,
+        slot.controller_number,
+        slot.location,
+        slot.controller_type.as_str())
+}
+
+fn iso_str(iso: &Path) -> Result<&str> {
+    if !iso.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("iso")) {
+        return Err(HypervError::new(format!("'{}' is not an ISO image", iso.display())))
+    }
+    iso.to_str().ok_or_else(|| HypervError::new("Bad path"))
+}
@@ -0,0 +1,63 @@
+use crate::ErrorKind;
+use serde_derive::Deserialize;
+
+// Prefixes the line carrying the error record among whatever else the command wrote to stderr
+const MARKER: &str = "hyperv_rs-error-record:";
+
+// PowerShell expression turning the error record in $_ into a marked line of JSON
+pub(crate) const RECORD_EXPRESSION: &str = "('hyperv_rs-error-record:' + ([pscustomobject]@{ FullyQualifiedErrorId = [string]$_.FullyQualifiedErrorId; Category = $_.CategoryInfo.Category.ToString(); HResult = $_.Exception.HResult; Message = $_.Exception.Message } | convertto-json -Compress))";
+
+// The error record of the terminating error a command failed with. Category is PowerShell's
+// ErrorCategory, e.g. ObjectNotFound or PermissionDenied, and FullyQualifiedErrorId names the error
+// and the cmdlet that raised it, e.g. InvalidParameter,Microsoft.HyperV.PowerShell.Commands.GetVM
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ErrorRecord {
+    #[serde(rename = "FullyQualifiedErrorId")]
+    pub fully_qualified_error_id: String,
+    #[serde(rename = "Category")]
+    pub category: String,
+    #[serde(rename = "HResult")]
+    pub hresult: Option<i32>,
+    #[serde(rename = "Message")]
+    pub message: String,
+}
+
+impl ErrorRecord {
+    pub(crate) fn kind(&self) -> ErrorKind {
+        match self.category.as_str() {
+            "ObjectNotFound" => ErrorKind::NotFound,
+            "PermissionDenied" => ErrorKind::PermissionDenied,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+// Reports the terminating error, if any, on stderr before failing as the command would have anyway
+pub(crate) fn wrap(command: &str) -> String {
+    format!("try {{ {} }} catch {{ [Console]::Error.WriteLine({}); throw }}", command, RECORD_EXPRESSION)
+}
+
+// The last record wins since an error from the alternate account's process is passed on by the outer one
+pub(crate) fn parse(output: &str) -> Option<ErrorRecord> {
+    output.lines()
+        .rev()
+        .filter_map(|line| line.trim().strip_prefix(MARKER))
+        .find_map(|json| serde_json::from_str(json).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_record_among_other_output() {
+        let stderr = "get-vm : Hyper-V was unable to find a virtual machine with name \"x\".\r\n\
+            hyperv_rs-error-record:{\"FullyQualifiedErrorId\":\"InvalidParameter,Microsoft.HyperV.PowerShell.Commands.GetVM\",\"Category\":\"ObjectNotFound\",\"HResult\":-2146233087,\"Message\":\"Not found\"}\r\n\
+            At line:1 char:7\r\n";
+        let record = parse(stderr).unwrap();
+        assert_eq!(record.category, "ObjectNotFound");
+        assert_eq!(record.hresult, Some(-2146233087));
+        assert_eq!(record.kind(), ErrorKind::NotFound);
+        assert!(parse("get-vm : failed").is_none());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{Hyperv, HypervError, Result, VmId, ps_quote};
+use crate::{progress, remote};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EXPORT_CHECKPOINT_NAME: &str = "hyperv_rs export checkpoint";
+const WRITE_PROBE_FILE_NAME: &str = ".hyperv_rs_write_probe";
+
+// Export-VM and Export-VMSnapshot write the configuration to <dest>\<vm name>\Virtual Machines\<vm id>.vmcx
+// (.xml on hosts older than Windows Server 2016). Expects $vm and $dest to be set
+const FIND_EXPORTED_CONFIG: &str =
+    "(get-childitem -LiteralPath (join-path (join-path $dest $vm.Name) 'Virtual Machines') -File -ErrorAction Stop |
+        where-object { $_.BaseName -eq $vm.Id.ToString() -and ($_.Extension -eq '.vmcx' -or $_.Extension -eq '.xml') } |
+        select-object -First 1).FullName | convertto-json";
+
+impl Hyperv {
+    // Returns the path of the exported configuration file, which can be passed to import_vm() or compare_vm()
+    pub fn export_vm<P: AsRef<Path>>(id: &VmId, dest_dir: P) -> Result<PathBuf> {
+        let dest_dir = Self::validate_export_dir(dest_dir.as_ref())?;
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop; $dest = {};
+            {} | out-null;
+            {}",
+            id,
+            ps_quote(dest_dir),
+            progress::tracked("$vm | export-vm -Path $dest -ErrorAction Stop"),
+            FIND_EXPORTED_CONFIG);
+        Self::run_export(&command)
+    }
+
+    // Exports a running VM in an application-consistent state by exporting a production checkpoint of it
+    // instead of the VM itself. Checkpoint creation fails rather than falling back to a standard checkpoint
+    // if the guest cannot quiesce (e.g. integration services are missing). The checkpoint is always removed.
+    // Returns the path of the exported configuration file like export_vm()
+    pub fn export_vm_consistent<P: AsRef<Path>>(id: &VmId, dest_dir: P) -> Result<PathBuf> {
+        let dest_dir = Self::validate_export_dir(dest_dir.as_ref())?;
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop; $dest = {};
+            $type = $vm.CheckpointType;
+            $vm | set-vm -CheckpointType ProductionOnly -ErrorAction Stop;
+            try {{
+                $checkpoint = $vm | checkpoint-vm -SnapshotName '{}' -Passthru -ErrorAction Stop
+            }} finally {{
+                $vm | set-vm -CheckpointType $type -ErrorAction Stop
+            }}
+            try {{
+                {} | out-null
+            }} finally {{
+                $checkpoint | remove-vmsnapshot -Confirm:$false -ErrorAction Stop
+            }}
+            {}",
+            id,
+            ps_quote(dest_dir),
+            EXPORT_CHECKPOINT_NAME,
+            progress::tracked("$checkpoint | export-vmsnapshot -Path $dest -ErrorAction Stop"),
+            FIND_EXPORTED_CONFIG);
+        Self::run_export(&command)
+    }
+
+    fn run_export(command: &str) -> Result<PathBuf> {
+        let config: Option<PathBuf> = Self::spawn_mutation_and_deserialize(command)?;
+        config.ok_or_else(|| HypervError::new("Exported configuration file not found in the destination directory"))
+    }
+
+    // The export itself is written by the Virtual Machine Management Service, so this only catches
+    // destinations that are plainly unusable
+    fn validate_export_dir(dest_dir: &Path) -> Result<&str> {
+        // The destination is on the remote host, which the Virtual Machine Management Service checks anyway
+        if !remote::is_local() {
+            return dest_dir.to_str().ok_or_else(|| HypervError::new("Bad path"))
+        }
+        if !dest_dir.is_dir() {
+            return Err(HypervError::new(format!("Export destination '{}' is not an existing directory", dest_dir.display())))
+        }
+
+        let probe = dest_dir.join(WRITE_PROBE_FILE_NAME);
+        fs::write(&probe, b"")
+            .map_err(|e| HypervError::new(format!("Export destination '{}' is not writable: {}", dest_dir.display(), e)))?;
+        let _ = fs::remove_file(&probe);
+
+        dest_dir.to_str().ok_or_else(|| HypervError::new("Bad path"))
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{ControllerType, DiskSlot, ErrorKind, Hyperv, HypervError, Result, VmId, VmProp, ps_quote};
+use serde_derive::Deserialize;
+
+// Turns a BootOrder entry of Get-VMFirmware into the key BootDevice::from_key() parses
+const BOOT_ENTRY_KEY_FUNCTION: &str =
+    "function boot-entry-key($entry) {
+        $device = $entry.Device;
+        switch ($entry.BootType.ToString()) {
+            'Network' { 'Network:' + $device.Name }
+            'Drive' { $device.GetType().Name + ':' + $device.ControllerType.ToString() + ':' + $device.ControllerNumber + ':' + $device.ControllerLocation }
+            default { 'File:' + $entry.Description }
+        }
+    }";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootDevice {
+    HardDisk(DiskSlot),
+    Dvd(DiskSlot),
+    // Network adapter by name
+    Network(String),
+    // A boot loader registered in the firmware, like "Windows Boot Manager", by description
+    File(String),
+}
+
+impl BootDevice {
+    fn key(&self) -> String {
+        match self {
+            BootDevice::HardDisk(slot) => format!("HardDiskDrive:{}:{}:{}", slot.controller_type.as_str(), slot.controller_number, slot.location),
+            BootDevice::Dvd(slot) => format!("DvdDrive:{}:{}:{}", slot.controller_type.as_str(), slot.controller_number, slot.location),
+            BootDevice::Network(name) => format!("Network:{}", name),
+            BootDevice::File(description) => format!("File:{}", description),
+        }
+    }
+
+    fn from_key(key: &str) -> Result<Self> {
+        let mut parts = key.splitn(2, ':');
+        let (kind, rest) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        match kind {
+            "Network" => Ok(BootDevice::Network(rest.to_owned())),
+            "File" => Ok(BootDevice::File(rest.to_owned())),
+            "HardDiskDrive" => Ok(BootDevice::HardDisk(parse_slot(rest)?)),
+            "DvdDrive" => Ok(BootDevice::Dvd(parse_slot(rest)?)),
+            _ => Err(HypervError::new(format!("Unexpected boot entry '{}'", key))),
+        }
+    }
+}
+
+fn parse_slot(s: &str) -> Result<DiskSlot> {
+    let parts: Vec<&str> = s.split(':').collect();
+    let invalid = || HypervError::new(format!("Invalid drive location '{}' in boot entry", s));
+    if parts.len() != 3 {
+        return Err(invalid())
+    }
+    Ok(DiskSlot {
+        controller_type: ControllerType::parse(parts[0])?,
+        controller_number: parts[1].parse().map_err(|_| invalid())?,
+        location: parts[2].parse().map_err(|_| invalid())?,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkBootProtocol {
+    IPv4,
+    IPv6,
+}
+
+// The certificates Secure Boot trusts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecureBootTemplate {
+    MicrosoftWindows,
+    // Needed by most Linux distributions, whose shim loaders are signed by the Microsoft UEFI CA
+    MicrosoftUEFICertificateAuthority,
+    OpenSourceShieldedVM,
+    // Templates added by newer Hyper-V versions, by name
+    Other(String),
+}
+
+impl SecureBootTemplate {
+    fn as_str(&self) -> &str {
+        match self {
+            SecureBootTemplate::MicrosoftWindows => "MicrosoftWindows",
+            SecureBootTemplate::MicrosoftUEFICertificateAuthority => "MicrosoftUEFICertificateAuthority",
+            SecureBootTemplate::OpenSourceShieldedVM => "OpenSourceShieldedVM",
+            SecureBootTemplate::Other(name) => name,
+        }
+    }
+
+    fn from(name: &str) -> Self {
+        match name {
+            "MicrosoftWindows" => SecureBootTemplate::MicrosoftWindows,
+            "MicrosoftUEFICertificateAuthority" => SecureBootTemplate::MicrosoftUEFICertificateAuthority,
+            "OpenSourceShieldedVM" => SecureBootTemplate::OpenSourceShieldedVM,
+            name => SecureBootTemplate::Other(name.to_owned()),
+        }
+    }
+}
+
+// Generation 2 (UEFI) firmware settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Firmware {
+    pub boot_order: Vec<BootDevice>,
+    pub preferred_network_boot_protocol: NetworkBootProtocol,
+    pub secure_boot: bool,
+    pub secure_boot_template: SecureBootTemplate,
+}
+
+#[derive(Deserialize)]
+struct RawFirmware {
+    #[serde(rename = "BootOrder")]
+    boot_order: Vec<String>,
+    #[serde(rename = "PreferredNetworkBootProtocol")]
+    preferred_network_boot_protocol: String,
+    #[serde(rename = "SecureBoot")]
+    secure_boot: String,
+    #[serde(rename = "SecureBootTemplate")]
+    secure_boot_template: String,
+}
+
+impl Hyperv {
+    pub fn get_firmware(id: &VmId) -> Result<Firmware> {
+        Self::require_generation_2(id)?;
+        let command = format!(
+            "{}
+            $firmware = get-vm -Id '{}' -ErrorAction Stop | get-vmfirmware -ErrorAction Stop;
+            [pscustomobject]@{{
+                BootOrder = @($firmware.BootOrder | foreach-object {{ boot-entry-key $_ }});
+                PreferredNetworkBootProtocol = $firmware.PreferredNetworkBootProtocol.ToString();
+                SecureBoot = $firmware.SecureBoot.ToString();
+                SecureBootTemplate = $firmware.SecureBootTemplate
+            }} | convertto-json",
+            BOOT_ENTRY_KEY_FUNCTION,
+            id);
+        let raw: Option<RawFirmware> = Self::spawn_and_deserialize(&command)?;
+        let raw = raw.ok_or_else(|| HypervError::new("Get-VMFirmware returned no output"))?;
+
+        Ok(Firmware {
+            boot_order: raw.boot_order.iter().map(|k| BootDevice::from_key(k)).collect::<Result<_>>()?,
+            preferred_network_boot_protocol: match raw.preferred_network_boot_protocol.as_str() {
+                "IPv4" => NetworkBootProtocol::IPv4,
+                "IPv6" => NetworkBootProtocol::IPv6,
+                p => return Err(HypervError::new(format!("Unexpected network boot protocol '{}'", p))),
+            },
+            secure_boot: raw.secure_boot == "On",
+            secure_boot_template: SecureBootTemplate::from(&raw.secure_boot_template),
+        })
+    }
+
+    // Moves the given devices to the front of the boot order, in the given order. Devices not listed
+    // keep their relative order after them. E.g. pass the network adapter to PXE boot, then the disk
+    pub fn set_boot_order(id: &VmId, devices: &[BootDevice]) -> Result<()> {
+        Self::require_generation_2(id)?;
+        let keys = devices.iter().map(|d| ps_quote(&d.key())).collect::<Vec<_>>().join(",");
+        let command = format!(
+            "{}
+            $vm = get-vm -Id '{}' -ErrorAction Stop;
+            $entries = @(($vm | get-vmfirmware -ErrorAction Stop).BootOrder);
+            $order = @();
+            foreach ($key in @({})) {{
+                $entry = $entries | where-object {{ (boot-entry-key $_) -eq $key }} | select-object -First 1;
+                if (-not $entry) {{ throw \"The VM has no boot device $key\" }}
+                $order += $entry
+            }}
+            $order += @($entries | where-object {{ $order -notcontains $_ }});
+            $vm | set-vmfirmware -BootOrder $order -ErrorAction Stop",
+            BOOT_ENTRY_KEY_FUNCTION,
+            id,
+            keys);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    pub fn set_preferred_network_boot_protocol(id: &VmId, protocol: NetworkBootProtocol) -> Result<()> {
+        Self::require_generation_2(id)?;
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | set-vmfirmware -PreferredNetworkBootProtocol {} -ErrorAction Stop",
+            id,
+            match protocol {
+                NetworkBootProtocol::IPv4 => "IPv4",
+                NetworkBootProtocol::IPv6 => "IPv6",
+            });
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // The VM must be off
+    pub fn enable_secure_boot(id: &VmId, template: &SecureBootTemplate) -> Result<()> {
+        Self::require_generation_2(id)?;
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | set-vmfirmware -EnableSecureBoot On -SecureBootTemplate {} -ErrorAction Stop",
+            id,
+            ps_quote(template.as_str()));
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // For guests whose boot loaders are not signed by anything a template trusts. The VM must be off
+    pub fn disable_secure_boot(id: &VmId) -> Result<()> {
+        Self::require_generation_2(id)?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | set-vmfirmware -EnableSecureBoot Off -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Generation 1 VMs have a BIOS, configured through Set-VMBios, instead
+    pub(crate) fn require_generation_2(id: &VmId) -> Result<()> {
+        let vm = Self::get_vm_with(id, &[VmProp::Generation])?;
+        if vm.generation != Some(2) {
+            return Err(HypervError::with_kind(ErrorKind::NotSupported, "Only Generation 2 VMs have UEFI firmware settings"))
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_device_keys_round_trip() {
+        let devices = vec![
+            BootDevice::HardDisk(DiskSlot { controller_type: ControllerType::Scsi, controller_number: 0, location: 1 }),
+            BootDevice::Dvd(DiskSlot { controller_type: ControllerType::Scsi, controller_number: 1, location: 0 }),
+            BootDevice::Network("Network Adapter: PXE".to_owned()),
+            BootDevice::File("Windows Boot Manager".to_owned()),
+        ];
+        for device in devices {
+            assert_eq!(BootDevice::from_key(&device.key()).unwrap(), device);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_boot_entries() {
+        assert!(BootDevice::from_key("Floppy:0").is_err());
+        assert!(BootDevice::from_key("HardDiskDrive:SCSI:0").is_err());
+    }
+}
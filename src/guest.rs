@@ -0,0 +1,72 @@
+use crate::{Credential, ErrorKind, Hyperv, HypervError, IntegrationService, Result, VmRef, ps_quote};
+use crate::config::encode_command;
+use serde_derive::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct GuestOutput {
+    #[serde(rename = "Stdout")]
+    pub stdout: String,
+    #[serde(rename = "Stderr")]
+    pub stderr: String,
+    #[serde(rename = "ExitCode")]
+    pub exit_code: i32,
+}
+
+impl Hyperv {
+    // Runs a PowerShell script in the guest over PowerShell Direct, which needs no network connection to
+    // the guest but only works with Windows 10 / Server 2016 or later guests. The script runs in its own
+    // powershell.exe so its output and exit code are returned as is. A non-zero exit code is not an error
+    pub fn invoke_in_guest<'a, V: Into<VmRef<'a>>>(vm: V, credential: &Credential, script: &str) -> Result<GuestOutput> {
+        let id = vm.into().resolve()?;
+        let (_scope, credential) = credential.expose();
+        let command = format!(
+            "invoke-command -VMId '{}' -Credential {} -ArgumentList '{}' -ErrorAction Stop -ScriptBlock {{
+                param($encoded)
+                $out = [IO.Path]::GetTempFileName(); $err = [IO.Path]::GetTempFileName();
+                try {{
+                    $p = start-process powershell.exe -NoNewWindow -Wait -PassThru -RedirectStandardOutput $out -RedirectStandardError $err -ArgumentList '-NoProfile','-NonInteractive','-EncodedCommand',$encoded;
+                    [pscustomobject]@{{
+                        Stdout = [IO.File]::ReadAllText($out);
+                        Stderr = [IO.File]::ReadAllText($err);
+                        ExitCode = $p.ExitCode
+                    }}
+                }} finally {{
+                    remove-item -Path $out,$err -ErrorAction SilentlyContinue
+                }}
+            }} | select-object -property Stdout,Stderr,ExitCode | convertto-json",
+            id,
+            credential,
+            encode_command(script));
+        let output: Option<GuestOutput> = Self::spawn_and_deserialize(&command)?;
+        output.ok_or_else(|| HypervError::new("Invoke-Command returned no output"))
+    }
+
+    // Copies a host file into the running guest through the Guest Service Interface integration service,
+    // enabling the service first if enable_service is set. create_full_path creates missing directories of
+    // dest. Fails if dest already exists
+    pub fn copy_file_to_guest<'a, V, S, D>(vm: V, source: S, dest: D, create_full_path: bool, enable_service: bool) -> Result<()>
+        where V: Into<VmRef<'a>>, S: AsRef<Path>, D: AsRef<Path> {
+        let id = vm.into().resolve()?;
+        let source = source.as_ref().to_str().ok_or_else(|| HypervError::new("Bad source path"))?;
+        let dest = dest.as_ref().to_str().ok_or_else(|| HypervError::new("Bad destination path"))?;
+
+        let service = IntegrationService::GuestServiceInterface;
+        let enabled = Self::list_integration_services(id)?.iter().any(|s| s.service == service && s.enabled);
+        if !enabled {
+            if !enable_service {
+                return Err(HypervError::with_kind(ErrorKind::NotSupported, "The Guest Service Interface integration service of the VM is disabled"))
+            }
+            Self::enable_integration_service(id, &service)?;
+        }
+
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | copy-vmfile -SourcePath {} -DestinationPath {} -FileSource Host {} -ErrorAction Stop",
+            id,
+            ps_quote(source),
+            ps_quote(dest),
+            if create_full_path { "-CreateFullPath" } else { "" });
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{Hyperv, HypervError, Result, ps_quote};
+use serde_derive::Deserialize;
+
+// What a VM needs from the host to start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmRequirements {
+    pub memory_bytes: u64,
+    pub processor_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlacementVerdict {
+    Fits,
+    InsufficientMemory { required: u64, available: u64 },
+    TooManyProcessors { requested: u32, available: u32 },
+}
+
+#[derive(Deserialize)]
+struct HostCapacity {
+    #[serde(rename = "LogicalProcessorCount")]
+    logical_processor_count: u32,
+    #[serde(rename = "FreeMemory")]
+    free_memory: u64,
+    // Memory the host keeps back from VMs. Configured in megabytes in the registry
+    #[serde(rename = "MemoryReserveMB")]
+    memory_reserve_mb: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumaNode {
+    pub id: u32,
+    pub memory_total_bytes: u64,
+    pub memory_available_bytes: u64,
+    pub processor_ids: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+struct RawNumaNode {
+    #[serde(rename = "NodeId")]
+    node_id: u32,
+    #[serde(rename = "MemoryTotal")]
+    memory_total_mb: u64,
+    #[serde(rename = "MemoryAvailable")]
+    memory_available_mb: u64,
+    #[serde(rename = "ProcessorIds")]
+    processor_ids: Vec<u32>,
+}
+
+// Logical processor ids as Hyper-V and cpugroups.exe number them: processor groups one after another,
+// each group's processors by their index within it. Neither Get-VMHostNumaNode nor WMI says which
+// processors belong to a node, so this asks Windows directly. GetNumaNodeProcessorMask2 knows about nodes
+// spanning more than one processor group but only exists on Windows Server 2022 and later
+const NUMA_PROCESSORS_SOURCE: &str = "
+using System;
+using System.Collections.Generic;
+using System.ComponentModel;
+using System.Runtime.InteropServices;
+public static class HypervRsNuma {
+    [StructLayout(LayoutKind.Sequential)]
+    struct GroupAffinity {
+        public UIntPtr Mask;
+        public ushort Group;
+        public ushort Reserved0, Reserved1, Reserved2;
+    }
+
+    [DllImport(\"kernel32.dll\", SetLastError = true)]
+    static extern bool GetNumaNodeProcessorMask2(ushort node, [Out] GroupAffinity[] affinities, ushort count, out ushort required);
+    [DllImport(\"kernel32.dll\", SetLastError = true)]
+    static extern bool GetNumaNodeProcessorMaskEx(ushort node, out GroupAffinity affinity);
+    [DllImport(\"kernel32.dll\")]
+    static extern uint GetActiveProcessorCount(ushort group);
+
+    public static uint[] Processors(ushort node) {
+        GroupAffinity[] affinities;
+        try {
+            ushort required;
+            GetNumaNodeProcessorMask2(node, null, 0, out required);
+            affinities = new GroupAffinity[required];
+            if (!GetNumaNodeProcessorMask2(node, affinities, required, out required)) { throw new Win32Exception(); }
+        } catch (EntryPointNotFoundException) {
+            GroupAffinity affinity;
+            if (!GetNumaNodeProcessorMaskEx(node, out affinity)) { throw new Win32Exception(); }
+            affinities = new GroupAffinity[] { affinity };
+        }
+
+        List<uint> ids = new List<uint>();
+        foreach (GroupAffinity affinity in affinities) {
+            uint first = 0;
+            for (ushort group = 0; group < affinity.Group; group++) { first += GetActiveProcessorCount(group); }
+            ulong mask = affinity.Mask.ToUInt64();
+            for (int bit = 0; bit < 64; bit++) {
+                if ((mask & (1UL << bit)) != 0) { ids.Add(first + (uint)bit); }
+            }
+        }
+        ids.Sort();
+        return ids.ToArray();
+    }
+}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulerType {
+    Classic,
+    Core,
+    Root,
+}
+
+impl SchedulerType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SchedulerType::Classic => "Classic",
+            SchedulerType::Core => "Core",
+            SchedulerType::Root => "Root",
+        }
+    }
+
+    fn from_bcd(s: &str) -> Option<Self> {
+        match s {
+            "Classic" => Some(SchedulerType::Classic),
+            "Core" => Some(SchedulerType::Core),
+            "Root" => Some(SchedulerType::Root),
+            _ => None,
+        }
+    }
+
+    // As reported by the hypervisor in its launch event
+    fn from_event_code(code: u32) -> Option<Self> {
+        match code {
+            1 | 2 => Some(SchedulerType::Classic),
+            3 => Some(SchedulerType::Core),
+            4 => Some(SchedulerType::Root),
+            _ => None,
+        }
+    }
+}
+
+// The scheduler the hypervisor booted with and the one it will use after the next boot.
+// None when it cannot be determined, e.g. the hypervisor's launch event has rolled out of the log or the BCD store has no setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerStatus {
+    pub running: Option<SchedulerType>,
+    pub configured: Option<SchedulerType>,
+}
+
+impl SchedulerStatus {
+    pub fn pending_reboot(&self) -> bool {
+        self.configured.is_some() && self.configured != self.running
+    }
+}
+
+// Whether VMs on this host can use SR-IOV. If not, Hyper-V says why, e.g. missing chipset or BIOS support
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct IovSupport {
+    #[serde(rename = "IovSupport")]
+    pub supported: bool,
+    #[serde(rename = "IovSupportReasons", default)]
+    pub reasons: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct RawSchedulerStatus {
+    #[serde(rename = "Running")]
+    running: Option<u32>,
+    #[serde(rename = "Configured")]
+    configured: Option<String>,
+}
+
+impl Hyperv {
+    pub fn get_iov_support() -> Result<IovSupport> {
+        let support: Option<IovSupport> = Self::spawn_and_deserialize(
+            "get-vmhost -ErrorAction Stop | select-object -property IovSupport,@{Name='IovSupportReasons';Expression={@($_.IovSupportReasons)}} | convertto-json")?;
+        support.ok_or_else(|| HypervError::new("Get-VMHost returned no output"))
+    }
+
+    // Checks whether a VM with the given requirements could be started on this host right now
+    pub fn can_place(requirements: &VmRequirements) -> Result<PlacementVerdict> {
+        let command = "$reserve = (get-itemproperty -Path 'HKLM:\\SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Virtualization' -Name MemoryReserve -ErrorAction SilentlyContinue).MemoryReserve;
+            [pscustomobject]@{
+                LogicalProcessorCount = (get-vmhost).LogicalProcessorCount;
+                FreeMemory = [uint64](get-ciminstance Win32_OperatingSystem).FreePhysicalMemory * 1KB;
+                MemoryReserveMB = if ($reserve) { [uint64]$reserve } else { 0 }
+            } | convertto-json";
+        let capacity: Option<HostCapacity> = Self::spawn_and_deserialize(command)?;
+        let capacity = capacity.ok_or_else(|| HypervError::new("Host capacity query returned no output"))?;
+
+        if requirements.processor_count > capacity.logical_processor_count {
+            return Ok(PlacementVerdict::TooManyProcessors { requested: requirements.processor_count, available: capacity.logical_processor_count })
+        }
+
+        let available = capacity.free_memory.saturating_sub(capacity.memory_reserve_mb * 1024 * 1024);
+        if requirements.memory_bytes > available {
+            return Ok(PlacementVerdict::InsufficientMemory { required: requirements.memory_bytes, available })
+        }
+
+        Ok(PlacementVerdict::Fits)
+    }
+
+    pub fn get_host_numa_topology() -> Result<Vec<NumaNode>> {
+        Self::require_full_language("Reading the NUMA topology")?;
+        let command = format!(
+            "add-type -TypeDefinition {} -ErrorAction Stop;
+            convertto-json -InputObject @(get-vmhostnumanode | sort-object -Property NodeId |
+                select-object -property NodeId,MemoryTotal,MemoryAvailable,@{{Name='ProcessorIds';Expression={{@([HypervRsNuma]::Processors([uint16]$_.NodeId))}}}})",
+            ps_quote(NUMA_PROCESSORS_SOURCE));
+        let raw: Vec<RawNumaNode> = Self::spawn_and_deserialize_list(&command)?;
+
+        let nodes = raw.into_iter().map(|node| NumaNode {
+            id: node.node_id,
+            memory_total_bytes: node.memory_total_mb * 1024 * 1024,
+            memory_available_bytes: node.memory_available_mb * 1024 * 1024,
+            processor_ids: node.processor_ids,
+        }).collect();
+
+        Ok(nodes)
+    }
+
+    pub fn get_scheduler_type() -> Result<SchedulerStatus> {
+        let command = "$event = get-winevent -FilterHashtable @{ ProviderName = 'Microsoft-Windows-Hyper-V-Hypervisor'; Id = 2 } -MaxEvents 1 -ErrorAction SilentlyContinue;
+            $running = $null;
+            if ($event -and $event.Message -match '0x([0-9a-fA-F]+)') { $running = [Convert]::ToUInt32($matches[1], 16) }
+            $configured = $null;
+            $line = bcdedit /enum '{current}' | where-object { $_ -match '^hypervisorschedulertype\\s+(\\S+)' } | select-object -First 1;
+            if ($line) { $configured = $matches[1] }
+            [pscustomobject]@{ Running = $running; Configured = $configured } | convertto-json";
+        let raw: Option<RawSchedulerStatus> = Self::spawn_and_deserialize(command)?;
+        let raw = raw.ok_or_else(|| HypervError::new("Scheduler type query returned no output"))?;
+        Ok(SchedulerStatus {
+            running: raw.running.and_then(SchedulerType::from_event_code),
+            configured: raw.configured.as_ref().and_then(|c| SchedulerType::from_bcd(c)),
+        })
+    }
+
+    // Takes effect on the next boot. The returned status tells whether a reboot is still pending
+    pub fn set_scheduler_type(scheduler: SchedulerType) -> Result<SchedulerStatus> {
+        Self::require_elevation()?;
+        let command = format!(
+            "bcdedit /set '{{current}}' hypervisorschedulertype {};
+            if ($LASTEXITCODE -ne 0) {{ throw 'bcdedit failed to set the hypervisor scheduler type' }}",
+            scheduler.as_str());
+        Self::spawn_mutation(&command)?;
+        Self::get_scheduler_type()
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{Hyperv, HypervError, Result, VmRef, ps_quote};
+use serde_derive::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrationService {
+    // Lets the host copy files into the guest
+    GuestServiceInterface,
+    Heartbeat,
+    KeyValuePairExchange,
+    Shutdown,
+    TimeSynchronization,
+    // Lets the guest quiesce its file systems for production checkpoints and backups
+    Vss,
+    // Services added by newer Hyper-V versions, by name
+    Other(String),
+}
+
+// Service names are localized, so the known services are matched on the last part of their Id instead
+const SERVICE_IDS: [(&str, IntegrationService); 6] = [
+    ("6C09BB55-D683-4DA0-8931-C9BF705F6480", IntegrationService::GuestServiceInterface),
+    ("84EAAE65-2F2E-45F5-9BB5-0E857DC8EB47", IntegrationService::Heartbeat),
+    ("2A34B1C2-FD73-4043-8A5B-DD2159BC743F", IntegrationService::KeyValuePairExchange),
+    ("9F8233AC-BE49-4C79-8EE3-E7E1985B2077", IntegrationService::Shutdown),
+    ("2497F4DE-E9FA-4204-80E4-4B75C46419C0", IntegrationService::TimeSynchronization),
+    ("5CED1297-4598-4915-A5FC-AD21BB4D02A4", IntegrationService::Vss),
+];
+
+impl IntegrationService {
+    fn from_raw(id: &str, name: &str) -> Self {
+        SERVICE_IDS.iter()
+            .find(|(suffix, _)| id.to_uppercase().ends_with(suffix))
+            .map(|(_, service)| service.clone())
+            .unwrap_or_else(|| IntegrationService::Other(name.to_owned()))
+    }
+
+    // Where-Object filter selecting this service
+    pub(crate) fn filter(&self) -> String {
+        match self {
+            IntegrationService::Other(name) => format!("$_.Name -eq {}", ps_quote(name)),
+            service => {
+                let (suffix, _) = SERVICE_IDS.iter().find(|(_, s)| s == service).expect("every known service has an Id");
+                format!("$_.Id -like '*{}'", suffix)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrationServiceState {
+    pub service: IntegrationService,
+    pub enabled: bool,
+    // As Hyper-V describes it, e.g. "OK" or "No Contact"
+    pub status: String,
+}
+
+// What the Heartbeat integration service last heard from the guest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeartbeatStatus {
+    OkApplicationsHealthy,
+    // The guest is up but does not report application health, e.g. older Windows or Linux guests
+    OkApplicationsUnknown,
+    OkApplicationsCritical,
+    // The guest has not answered yet, e.g. while it is still booting or it lacks integration components
+    NoContact,
+    // The guest answered before but stopped, e.g. because it hung or crashed
+    LostCommunication,
+    Paused,
+    Error,
+    Disabled,
+    // Also reported while the VM is off
+    Unknown,
+}
+
+impl HeartbeatStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "OkApplicationsHealthy" => HeartbeatStatus::OkApplicationsHealthy,
+            "OkApplicationsUnknown" => HeartbeatStatus::OkApplicationsUnknown,
+            "OkApplicationsCritical" => HeartbeatStatus::OkApplicationsCritical,
+            "NoContact" => HeartbeatStatus::NoContact,
+            "LostCommunication" => HeartbeatStatus::LostCommunication,
+            "Paused" => HeartbeatStatus::Paused,
+            "Error" => HeartbeatStatus::Error,
+            "Disabled" => HeartbeatStatus::Disabled,
+            _ => HeartbeatStatus::Unknown,
+        }
+    }
+
+    // True once the guest OS is up and answering, whatever the health of its applications
+    pub fn is_ok(&self) -> bool {
+        matches!(self, HeartbeatStatus::OkApplicationsHealthy | HeartbeatStatus::OkApplicationsUnknown | HeartbeatStatus::OkApplicationsCritical)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawIntegrationService {
+    #[serde(rename = "Id")]
+    id: String,
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Enabled")]
+    enabled: bool,
+    #[serde(rename = "PrimaryStatusDescription")]
+    status: Option<String>,
+}
+
+impl Hyperv {
+    pub fn list_integration_services<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<Vec<IntegrationServiceState>> {
+        let id = vm.into().resolve()?;
+        let command = format!(
+            "convertto-json -InputObject @(get-vm -Id '{}' -ErrorAction Stop | get-vmintegrationservice -ErrorAction Stop | select-object -property Id,Name,Enabled,PrimaryStatusDescription)",
+            id);
+        let services: Vec<RawIntegrationService> = Self::spawn_and_deserialize_list(&command)?;
+        Ok(services.into_iter()
+            .map(|s| IntegrationServiceState {
+                service: IntegrationService::from_raw(&s.id, &s.name),
+                enabled: s.enabled,
+                status: s.status.unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    // Unlike the VM state, which is Running as soon as the VM is powered on, this tells whether the guest has
+    // actually booted. Poll it until is_ok() to wait for a guest to come up
+    pub fn heartbeat<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<HeartbeatStatus> {
+        let id = vm.into().resolve()?;
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop; if ($vm.Heartbeat) {{ $vm.Heartbeat.ToString() | convertto-json }} else {{ 'Unknown' | convertto-json }}",
+            id);
+        let status: Option<String> = Self::spawn_and_deserialize(&command)?;
+        let status = status.ok_or_else(|| HypervError::new("Get-VM returned no heartbeat"))?;
+        Ok(HeartbeatStatus::from(&status))
+    }
+
+    // The guest must also run the matching integration component for the service to work
+    pub fn enable_integration_service<'a, V: Into<VmRef<'a>>>(vm: V, service: &IntegrationService) -> Result<()> {
+        Self::set_integration_service(vm.into(), service, "enable-vmintegrationservice")
+    }
+
+    pub fn disable_integration_service<'a, V: Into<VmRef<'a>>>(vm: V, service: &IntegrationService) -> Result<()> {
+        Self::set_integration_service(vm.into(), service, "disable-vmintegrationservice")
+    }
+
+    fn set_integration_service(vm: VmRef, service: &IntegrationService, cmdlet: &str) -> Result<()> {
+        let id = vm.resolve()?;
+        let command = format!(
+            "$service = get-vm -Id '{}' -ErrorAction Stop | get-vmintegrationservice -ErrorAction Stop | where-object {{ {} }};
+            if (-not $service) {{ throw 'The VM has no such integration service' }}
+            $service | {} -ErrorAction Stop",
+            id,
+            service.filter(),
+            cmdlet);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_services_by_id() {
+        let id = "Microsoft:4A6D5B8C-1234-4F0A-9C3B-0123456789AB\\84eaae65-2f2e-45f5-9bb5-0e857dc8eb47";
+        assert_eq!(IntegrationService::from_raw(id, "Heartbeat"), IntegrationService::Heartbeat);
+        assert_eq!(
+            IntegrationService::from_raw("Microsoft:4A6D5B8C-1234-4F0A-9C3B-0123456789AB\\0", "Something New"),
+            IntegrationService::Other("Something New".to_owned()));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{HardDisk, Hyperv, HypervError, MemorySettings, NetworkAdapter, ProcessorSettings, Result, Vm, VmProp};
+use crate::disk::{RawHardDisk, HARD_DISK_PROJECTION};
+use crate::memory::MEMORY_PROJECTION;
+use crate::network::NETWORK_ADAPTER_PROJECTION;
+use crate::processor::PROCESSOR_PROJECTION;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bump whenever the shape of the exported document changes
+const INVENTORY_SCHEMA_VERSION: u32 = 2;
+
+// Collects every VM along with its configuration, plus the host's switches, in one PowerShell session.
+// Enums and dates are flattened to strings and unix milliseconds so the output is stable across hosts.
+// The per-VM projections are shared with get_full_inventory so the two cannot drift apart
+fn inventory_script() -> String {
+    format!(
+        "$vms = @(get-vm | foreach-object {{
+            $vm = $_;
+            [pscustomobject]@{{
+                Id = $vm.Id.ToString();
+                Name = $vm.Name;
+                State = $vm.State.ToString();
+                Generation = $vm.Generation;
+                Version = $vm.Version;
+                Notes = $vm.Notes;
+                Processor = get-vmprocessor -VM $vm | select-object -property {};
+                Memory = get-vmmemory -VM $vm | select-object -property {};
+                HardDisks = @(get-vmharddiskdrive -VM $vm | select-object -property {});
+                NetworkAdapters = @(get-vmnetworkadapter -VM $vm | select-object -property {});
+                Checkpoints = @(get-vmsnapshot -VM $vm | select-object -property @{{Name='Id';Expression={{$_.Id.ToString()}}}},Name,
+                    @{{Name='ParentId';Expression={{ if ($_.ParentSnapshotId) {{ $_.ParentSnapshotId.ToString() }} else {{ $null }} }}}},
+                    @{{Name='CreationTime';Expression={{([DateTimeOffset]$_.CreationTime).ToUnixTimeMilliseconds()}}}});
+            }}
+        }});
+        $switches = @(get-vmswitch | select-object -property @{{Name='Id';Expression={{$_.Id.ToString()}}}},Name,
+            @{{Name='SwitchType';Expression={{$_.SwitchType.ToString()}}}},NetAdapterInterfaceDescription);
+        [pscustomobject]@{{ Host = $env:COMPUTERNAME; VirtualMachines = $vms; Switches = $switches }} | convertto-json -Depth 6",
+        PROCESSOR_PROJECTION,
+        MEMORY_PROJECTION,
+        HARD_DISK_PROJECTION,
+        NETWORK_ADAPTER_PROJECTION)
+}
+
+// A VM along with the configuration of its processors, memory, disks and adapters
+#[derive(Debug, Clone)]
+pub struct VmDetails {
+    pub vm: Vm,
+    pub processor: ProcessorSettings,
+    pub memory: MemorySettings,
+    pub hard_disks: Vec<HardDisk>,
+    pub network_adapters: Vec<NetworkAdapter>,
+}
+
+#[derive(Deserialize)]
+struct RawVmDetails {
+    #[serde(rename = "Vm")]
+    vm: Vm,
+    #[serde(rename = "Processor")]
+    processor: ProcessorSettings,
+    #[serde(rename = "Memory")]
+    memory: MemorySettings,
+    #[serde(rename = "HardDisks")]
+    hard_disks: Vec<RawHardDisk>,
+    #[serde(rename = "NetworkAdapters")]
+    network_adapters: Vec<NetworkAdapter>,
+}
+
+impl RawVmDetails {
+    fn into_details(self) -> Result<VmDetails> {
+        Ok(VmDetails {
+            vm: self.vm,
+            processor: self.processor,
+            memory: self.memory,
+            hard_disks: self.hard_disks.into_iter().map(RawHardDisk::into_hard_disk).collect::<Result<_>>()?,
+            network_adapters: self.network_adapters,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct InventoryDocument {
+    schema_version: u32,
+    generated_at: u64,
+    inventory: serde_json::Value,
+}
+
+impl Hyperv {
+    // Everything about every VM in a single PowerShell session, rather than a process per VM and setting
+    pub fn get_full_inventory() -> Result<Vec<VmDetails>> {
+        let command = format!(
+            "convertto-json -Depth 4 -InputObject @(get-vm | foreach-object {{
+                $vm = $_;
+                [pscustomobject]@{{
+                    Vm = $vm | select-object -property {};
+                    Processor = get-vmprocessor -VM $vm | select-object -property {};
+                    Memory = get-vmmemory -VM $vm | select-object -property {};
+                    HardDisks = @(get-vmharddiskdrive -VM $vm | select-object -property {});
+                    NetworkAdapters = @(get-vmnetworkadapter -VM $vm | select-object -property {});
+                }}
+            }})",
+            VmProp::projection_of(&VmProp::ALL),
+            PROCESSOR_PROJECTION,
+            MEMORY_PROJECTION,
+            HARD_DISK_PROJECTION,
+            NETWORK_ADAPTER_PROJECTION);
+        let raw: Vec<RawVmDetails> = Self::spawn_and_deserialize_list(&command)?;
+        raw.into_iter().map(RawVmDetails::into_details).collect()
+    }
+
+    // Writes a single JSON document describing all VMs and switches on the host, for audits and
+    // for documenting a host before it is rebuilt
+    pub fn export_inventory<P: AsRef<Path>>(path: P) -> Result<()> {
+        let inventory: Option<serde_json::Value> = Self::spawn_and_deserialize(&inventory_script())?;
+        let generated_at = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_millis()))
+            .unwrap_or(0);
+        let document = InventoryDocument {
+            schema_version: INVENTORY_SCHEMA_VERSION,
+            generated_at,
+            inventory: inventory.ok_or_else(|| HypervError::new("Inventory query returned no output"))?,
+        };
+
+        let file = File::create(path.as_ref())
+            .map_err(|e| HypervError::new(format!("Failed to create inventory file '{}': {}", path.as_ref().display(), e)))?;
+        serde_json::to_writer_pretty(file, &document)
+            .map_err(|e| HypervError::new(format!("Failed to write inventory file: {}", e)))
+    }
+}
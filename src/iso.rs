@@ -0,0 +1,61 @@
+use crate::{Hyperv, HypervError, Result, ps_quote};
+use crate::remote;
+use std::path::Path;
+
+// ISO9660 | Joliet | UDF, so the image is readable by both old and new guests
+const FILE_SYSTEMS: u32 = 7;
+// IMAPI_MEDIA_TYPE_DISK. Lifts the size limit of CD media
+const MEDIA_TYPE_DISK: u32 = 13;
+const MAX_LABEL_LEN: usize = 32;
+
+// IMAPI2 hands back the image as a COM IStream which PowerShell cannot read by itself
+const ISO_WRITER_SOURCE: &str = "
+using System;
+using System.IO;
+using System.Runtime.InteropServices.ComTypes;
+public static class HypervRsIsoWriter {
+    public static void Write(string path, object image, int blockSize, int totalBlocks) {
+        IStream stream = (IStream)image;
+        byte[] buffer = new byte[blockSize];
+        using (FileStream file = File.Create(path)) {
+            for (int i = 0; i < totalBlocks; i++) {
+                stream.Read(buffer, blockSize, IntPtr.Zero);
+                file.Write(buffer, 0, blockSize);
+            }
+        }
+    }
+}";
+
+impl Hyperv {
+    // Builds an ISO image of the directory's contents using the Windows IMAPI2 file system imager,
+    // e.g. to hand a provisioning payload to a guest through its DVD drive
+    pub fn create_iso<P: AsRef<Path>, Q: AsRef<Path>>(source_dir: P, iso_path: Q, label: &str) -> Result<()> {
+        Self::require_full_language("ISO creation")?;
+        if remote::is_local() && !source_dir.as_ref().is_dir() {
+            return Err(HypervError::new("Source path does not point to a directory"))
+        }
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return Err(HypervError::new(format!("Invalid ISO label '{}'. Must be 1 to {} characters", label, MAX_LABEL_LEN)))
+        }
+
+        let source_dir = source_dir.as_ref().to_str().ok_or_else(|| HypervError::new("Bad path"))?;
+        let iso_path = iso_path.as_ref().to_str().ok_or_else(|| HypervError::new("Bad path"))?;
+        let command = format!(
+            "add-type -TypeDefinition {} -ErrorAction Stop;
+            $fsi = new-object -ComObject IMAPI2FS.MsftFileSystemImage;
+            $fsi.ChooseImageDefaultsForMediaType({});
+            $fsi.FileSystemsToCreate = {};
+            $fsi.VolumeName = {};
+            $fsi.Root.AddTree({}, $false);
+            $image = $fsi.CreateResultImage();
+            [HypervRsIsoWriter]::Write({}, $image.ImageStream, $image.BlockSize, $image.TotalBlocks)",
+            ps_quote(ISO_WRITER_SOURCE),
+            MEDIA_TYPE_DISK,
+            FILE_SYSTEMS,
+            ps_quote(label),
+            ps_quote(source_dir),
+            ps_quote(iso_path));
+        Self::spawn_and_wait(&command)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{Hyperv, HypervError, Result, VmId, VmRef, ps_quote};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+
+// Sizes of the guest side KVP buffers, in UTF-16 code units and excluding the terminating null
+const MAX_KEY_LEN: usize = 255;
+const MAX_VALUE_LEN: usize = 1023;
+
+// Turns the Msvm_KvpExchangeDataItem embedded instances KVP properties hold into Name/Data objects
+const KVP_ITEMS_FUNCTION: &str =
+    "function kvp-items($items) {
+        foreach ($item in @($items)) {
+            $props = ([xml]$item).INSTANCE.PROPERTY;
+            [pscustomobject]@{
+                Name = ($props | where-object { $_.NAME -eq 'Name' }).VALUE;
+                Data = ($props | where-object { $_.NAME -eq 'Data' }).VALUE
+            }
+        }
+    }";
+
+// Waits for the job a Msvm_VirtualSystemManagementService method may return and throws if it failed
+const WAIT_WMI_RESULT_FUNCTION: &str =
+    "function wait-wmi-result($result) {
+        if ($result.ReturnValue -eq 4096) {
+            $job = [wmi]$result.Job;
+            while ($job.JobState -ge 2 -and $job.JobState -le 4) {
+                start-sleep -Milliseconds 100;
+                $job = [wmi]$result.Job
+            }
+            if ($job.JobState -ne 7) { throw $job.ErrorDescription }
+        } elseif ($result.ReturnValue -ne 0) {
+            throw \"The KVP operation failed with return value $($result.ReturnValue)\"
+        }
+    }";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvpPool {
+    // Items the guest's integration components publish about it, e.g. FullyQualifiedDomainName or OSName.
+    // Only available while the guest is running
+    GuestIntrinsic,
+    // Items programs in the guest wrote for the host. Only available while the guest is running
+    Guest,
+    // Items the host wrote for the guest with set_kvp_item()
+    Host,
+    // Items kept on the host side only, invisible to the guest
+    HostOnly,
+}
+
+impl KvpPool {
+    fn items_expression(&self) -> &str {
+        match self {
+            KvpPool::GuestIntrinsic => "@($vm.GetRelated('Msvm_KvpExchangeComponent'))[0].GuestIntrinsicExchangeItems",
+            KvpPool::Guest => "@($vm.GetRelated('Msvm_KvpExchangeComponent'))[0].GuestExchangeItems",
+            KvpPool::Host => "@(@($vm.GetRelated('Msvm_KvpExchangeComponent'))[0].GetRelated('Msvm_KvpExchangeComponentSettingData'))[0].HostExchangeItems",
+            KvpPool::HostOnly => "@(@($vm.GetRelated('Msvm_KvpExchangeComponent'))[0].GetRelated('Msvm_KvpExchangeComponentSettingData'))[0].HostOnlyItems",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawKvpItem {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Data")]
+    data: Option<String>,
+}
+
+impl Hyperv {
+    pub fn get_kvp_items<'a, V: Into<VmRef<'a>>>(vm: V, pool: KvpPool) -> Result<HashMap<String, String>> {
+        let id = vm.into().resolve()?;
+        let command = format!(
+            "{}
+            {}
+            convertto-json -InputObject @(kvp-items ({}))",
+            KVP_ITEMS_FUNCTION,
+            get_wmi_vm(&id),
+            pool.items_expression());
+        let items: Vec<RawKvpItem> = Self::spawn_and_deserialize_list(&command)?;
+        Ok(items.into_iter().map(|i| (i.name, i.data.unwrap_or_default())).collect())
+    }
+
+    // Adds the item to the host pool or replaces its value. Linux guests with the KVP daemon see it in
+    // /var/lib/hyperv/.kvp_pool_0, Windows guests under HKLM\SOFTWARE\Microsoft\Virtual Machine\External
+    pub fn set_kvp_item<'a, V: Into<VmRef<'a>>>(vm: V, key: &str, value: &str) -> Result<()> {
+        validate_kvp_item(key, value)?;
+        let id = vm.into().resolve()?;
+        let command = format!(
+            "{}
+            {}
+            {}
+            $svc = get-wmiobject -Namespace root\\virtualization\\v2 -Class Msvm_VirtualSystemManagementService -ErrorAction Stop;
+            $item = ([wmiclass]'root\\virtualization\\v2:Msvm_KvpExchangeDataItem').CreateInstance();
+            $item.Name = {key};
+            $item.Data = {value};
+            $item.Source = 0;
+            $existing = @(kvp-items ({host_items}) | where-object {{ $_.Name -eq {key} }});
+            if ($existing) {{
+                wait-wmi-result $svc.ModifyKvpItems($vm, @($item.PSBase.GetText(1)))
+            }} else {{
+                wait-wmi-result $svc.AddKvpItems($vm, @($item.PSBase.GetText(1)))
+            }}",
+            KVP_ITEMS_FUNCTION,
+            WAIT_WMI_RESULT_FUNCTION,
+            get_wmi_vm(&id),
+            key = ps_quote(key),
+            value = ps_quote(value),
+            host_items = KvpPool::Host.items_expression());
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Does nothing if the host pool has no such item
+    pub fn remove_kvp_item<'a, V: Into<VmRef<'a>>>(vm: V, key: &str) -> Result<()> {
+        let id = vm.into().resolve()?;
+        let command = format!(
+            "{}
+            {}
+            {}
+            $existing = @(kvp-items ({host_items}) | where-object {{ $_.Name -eq {key} }});
+            if ($existing) {{
+                $svc = get-wmiobject -Namespace root\\virtualization\\v2 -Class Msvm_VirtualSystemManagementService -ErrorAction Stop;
+                $item = ([wmiclass]'root\\virtualization\\v2:Msvm_KvpExchangeDataItem').CreateInstance();
+                $item.Name = {key};
+                $item.Data = '';
+                $item.Source = 0;
+                wait-wmi-result $svc.RemoveKvpItems($vm, @($item.PSBase.GetText(1)))
+            }}",
+            KVP_ITEMS_FUNCTION,
+            WAIT_WMI_RESULT_FUNCTION,
+            get_wmi_vm(&id),
+            key = ps_quote(key),
+            host_items = KvpPool::Host.items_expression());
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+// Looks up the VM's Msvm_ComputerSystem as $vm
+fn get_wmi_vm(id: &VmId) -> String {
+    format!(
+        "$vm = get-wmiobject -Namespace root\\virtualization\\v2 -Class Msvm_ComputerSystem -Filter \"Name='{id}'\" -ErrorAction Stop;
+        if (-not $vm) {{ throw 'No VM with Id {id} exists' }}",
+        id = id)
+}
+
+fn validate_kvp_item(key: &str, value: &str) -> Result<()> {
+    if key.is_empty() {
+        return Err(HypervError::new("KVP key cannot be empty"))
+    }
+    if key.encode_utf16().count() > MAX_KEY_LEN {
+        return Err(HypervError::new(format!("KVP key '{}' is longer than {} characters", key, MAX_KEY_LEN)))
+    }
+    if value.encode_utf16().count() > MAX_VALUE_LEN {
+        return Err(HypervError::new(format!("Value of KVP key '{}' is longer than {} characters", key, MAX_VALUE_LEN)))
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_kvp_item_sizes() {
+        assert!(validate_kvp_item("role", "web").is_ok());
+        assert!(validate_kvp_item("", "web").is_err());
+        assert!(validate_kvp_item(&"k".repeat(MAX_KEY_LEN + 1), "web").is_err());
+        assert!(validate_kvp_item("role", &"v".repeat(MAX_VALUE_LEN)).is_ok());
+        assert!(validate_kvp_item("role", &"v".repeat(MAX_VALUE_LEN + 1)).is_err());
+    }
+}
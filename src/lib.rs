@@ -1,55 +1,220 @@
-use powershell_rs::{PsCommand, Stdio, PsProcess, Stdout};
+use powershell_rs::{PsCommand, Stdio, PsProcess};
 use failure::Fail;
-use serde_derive::Deserialize;
+use serde::{Deserialize, Deserializer};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
 use uuid::Uuid;
 use std::fmt;
 use std::path::Path;
-use std::io::{BufReader, BufRead};
+use std::str::FromStr;
+use std::time::Duration;
+
+mod adapter;
+mod batch;
+mod builder;
+mod cache;
+mod checkpoint;
+mod config;
+mod console;
+mod convert;
+mod cpugroups;
+mod credential;
+mod diagnostics;
+mod disk;
+mod dvd;
+mod error_record;
+mod export;
+mod firmware;
+mod guest;
+mod host;
+mod integration;
+mod inventory;
+mod iso;
+mod kvp;
+mod lifecycle;
+mod maintenance;
+mod memory;
+mod metering;
+mod migration;
+mod nat;
+mod naming;
+mod network;
+mod numa;
+mod operation;
+mod privileges;
+mod processor;
+mod progress;
+mod queue;
+mod remote;
+mod replication;
+#[cfg(feature = "server")]
+mod server;
+mod session;
+mod spec;
+mod switch;
+mod tags;
+mod tpm;
+mod vhd;
+#[cfg(feature = "wmi")]
+mod wmi_backend;
+
+pub use crate::adapter::{Bandwidth, PortMirroring, VlanMode};
+pub use crate::batch::Batch;
+pub use crate::builder::VmBuilder;
+pub use crate::checkpoint::{Checkpoint, CheckpointNode};
+pub use crate::cache::InventoryCache;
+pub use crate::cpugroups::{CpuGroup, CPU_CAP_UNLIMITED};
+pub use crate::credential::Credential;
+pub use crate::diagnostics::{DumpRetrieval, GuestCrash, GuestCrashKind};
+pub use crate::disk::{ControllerDrive, ControllerType, DiskSlot, HardDisk, ScsiController};
+pub use crate::dvd::DvdDrive;
+pub use crate::error_record::ErrorRecord;
+pub use crate::firmware::{BootDevice, Firmware, NetworkBootProtocol, SecureBootTemplate};
+pub use crate::guest::GuestOutput;
+pub use crate::host::{IovSupport, NumaNode, PlacementVerdict, SchedulerStatus, SchedulerType, VmRequirements};
+pub use crate::integration::{HeartbeatStatus, IntegrationService, IntegrationServiceState};
+pub use crate::inventory::VmDetails;
+pub use crate::kvp::KvpPool;
+pub use crate::lifecycle::StopMode;
+pub use crate::maintenance::{QuiesceAction, QuiescedVm, QuiesceOutcome, QuiescePolicy};
+pub use crate::memory::MemorySettings;
+pub use crate::metering::VmMetrics;
+pub use crate::migration::{MoveVmOptions, StoragePaths};
+pub use crate::naming::VmRef;
+pub use crate::nat::{NatNetwork, PortForward, Protocol};
+pub use crate::network::{AdapterAddresses, NetworkAdapter};
+pub use crate::numa::VmNumaSettings;
+pub use crate::operation::Operation;
+pub use crate::processor::ProcessorSettings;
+pub use crate::queue::Priority;
+pub use crate::remote::HypervHost;
+pub use crate::replication::{RecoveryPoint, Replication, ReplicationAuth, ReplicationHealth, ReplicationMode, ReplicationState};
+#[cfg(feature = "server")]
+pub use crate::server::Server;
+pub use crate::session::HypervSession;
+pub use crate::spec::{Drift, VmSpec};
+pub use crate::switch::{SwitchType, VSwitch, DEFAULT_SWITCH_ID};
+pub use crate::vhd::{NewVhdOptions, OptimizeMode, SectorSize, Vhd, VhdChainLink, VhdFormat, VhdInfo, VhdType};
 
 pub struct Hyperv;
 
 pub type Result<T> = std::result::Result<T, HypervError>;
 
 impl Hyperv {
+    // Every VM with all of its VmProp properties filled in. Use get_vms_with() to fetch only some, which
+    // is quicker on hosts with many VMs, or none beyond Id and Name
     pub fn get_vms() -> Result<Vec<Vm>> {
-        let process = Self::spawn("get-vm|select-object -property Id,Name |convertto-json")?;
-        let stdout = process.stdout().ok_or_else(|| HypervError::new("Could not access stdout of powershell process"))?;
-
-        let vms: Vec<Vm> = serde_json::from_reader(stdout)
-            .map_err(|e| HypervError::new(format!("Failed to parse powershell output: {}", e)))?;
+        Self::get_vms_with(&VmProp::ALL)
+    }
 
+    pub fn get_vms_with(props: &[VmProp]) -> Result<Vec<Vm>> {
+        #[cfg(feature = "wmi")]
+        {
+            if let Some(vms) = wmi_backend::get_vms(props, None)? {
+                return Ok(vms)
+            }
+        }
+        let command = format!("convertto-json -InputObject @(get-vm | select-object -property {})", VmProp::projection_of(props));
+        let vms: Vec<Vm> = Self::spawn_and_deserialize_list(&command)?;
         Ok(vms)
     }
 
+    // VMs whose name matches the PowerShell wildcard pattern, e.g. "web-*", filtered on the host so the
+    // rest are never fetched. Matching is case insensitive. No match is an empty list, not an error
+    pub fn get_vms_matching(pattern: &str) -> Result<Vec<Vm>> {
+        if pattern.trim().is_empty() {
+            return Err(HypervError::new("Name pattern cannot be empty"))
+        }
+        let command = format!(
+            "convertto-json -InputObject @(get-vm -Name {} -ErrorAction SilentlyContinue | select-object -property {})",
+            ps_quote(pattern),
+            VmProp::projection_of(&VmProp::ALL));
+        Self::spawn_and_deserialize_list(&command)
+    }
+
+    // Looks the VM up on the host rather than listing them all. Fails with ErrorKind::NotFound if no VM
+    // has the Id
+    pub fn get_vm_by_id(id: &VmId) -> Result<Vm> {
+        Self::get_vm_with(id, &VmProp::ALL)
+    }
+
+    pub(crate) fn get_vm_with(id: &VmId, props: &[VmProp]) -> Result<Vm> {
+        #[cfg(feature = "wmi")]
+        {
+            if let Some(vms) = wmi_backend::get_vms(props, Some(id))? {
+                return vms.into_iter().next().ok_or_else(|| vm_not_found(id))
+            }
+        }
+        // Get-VM fails with an error on unknown Ids only on some Windows versions, so absence is checked here
+        let command = format!("get-vm -Id '{}' -ErrorAction SilentlyContinue | select-object -property {} | convertto-json", id, VmProp::projection_of(props));
+        let vm: Option<Vm> = Self::spawn_and_deserialize(&command)?;
+        vm.ok_or_else(|| vm_not_found(id))
+    }
+
     pub fn import_vm<P: AsRef<Path>>(path: P, import_type: &ImportType) -> Result<()> {
         let path = Self::validate_file_path(path.as_ref())?;
         let command = &format!(
-            "import-vm -Path \"{}\" {};",
-        path,
-        Self::generate_import_vm_param_stub(import_type));
-        Self::spawn_and_wait(command)?;
+            "{};",
+        progress::tracked(&format!("import-vm -Path {} {}", ps_quote(path), Self::generate_import_vm_param_stub(import_type))));
+        Self::spawn_mutation(command)?;
         Ok(())
     }
 
+    // Imports the VM, then renames it and moves its adapters to local switches in the same PowerShell
+    // session. Adapters whose switch is missing on this host are remapped before import so that the
+    // import does not fail with VmIncompatibility::MissingSwitch. Returns the Id of the imported VM
+    pub fn import_vm_remapped<P: AsRef<Path>>(path: P, import_type: &ImportType, remap: &ImportRemap) -> Result<VmId> {
+        let path = Self::validate_file_path(path.as_ref())?;
+        let switch_map = remap.switches.iter()
+            .map(|(from, to)| format!("{} = {}", ps_quote(from), ps_quote(to)))
+            .collect::<Vec<_>>()
+            .join("; ");
+        let rename = match &remap.name {
+            Some(name) => format!("$vm | rename-vm -NewName {} -ErrorAction Stop;", ps_quote(name)),
+            None => "".to_owned(),
+        };
+
+        let command = format!(
+            "$report = compare-vm -Path {} {} -ErrorAction Stop;
+            $map = @{{ {} }};
+            foreach ($i in $report.Incompatibilities) {{
+                if ($i.MessageId -eq {} -and $map.ContainsKey($i.Source.SwitchName)) {{ $i.Source | connect-vmnetworkadapter -SwitchName $map[$i.Source.SwitchName] }}
+            }}
+            $vm = {};
+            $vm | get-vmnetworkadapter | where-object {{ $_.SwitchName -and $map.ContainsKey($_.SwitchName) }} |
+                foreach-object {{ connect-vmnetworkadapter -VMNetworkAdapter $_ -SwitchName $map[$_.SwitchName] -ErrorAction Stop }};
+            {}
+            $vm.Id.ToString() | convertto-json",
+            ps_quote(path),
+            Self::generate_import_vm_param_stub(import_type),
+            switch_map,
+            MISSING_SWITCH_MESSAGE_ID,
+            progress::tracked("import-vm -CompatibilityReport $report -ErrorAction Stop"),
+            rename);
+
+        let id: Option<VmId> = Self::spawn_mutation_and_deserialize(&command)?;
+        id.ok_or_else(|| HypervError::new("Import-VM did not return the imported VM"))
+    }
+
     pub fn compare_vm<P: AsRef<Path>>(path: P, import_type: &ImportType) -> Result<Vec<VmIncompatibility>> {
         let path = Self::validate_file_path(path.as_ref())?;
         let command = format!(
-            "$report = compare-vm -Path \"{}\" {};
+            "$report = compare-vm -Path {} {};
             if ($?) {{ $report.Incompatibilities | Format-Table -Property MessageId, Message -HideTableHeaders }}",
-        path,
+        ps_quote(path),
         Self::generate_import_vm_param_stub(import_type));
              
         let output = Self::spawn_and_wait(&command)?;
 
-        Self::map_lines(output, |line: &str| {
+        Self::map_lines(&output, |line: &str| {
             let line = line.trim();
             if line.is_empty() {
                 return Ok(None)
             }
             let mut parts = line.splitn(2, ' ');
-            let msg_id = parts.next().ok_or_else(|| HypervError { msg: "Failed to parse to VmIncomatibility. No MessageId in string".to_owned() })?;
-            let msg = parts.next().ok_or_else(|| HypervError { msg: "Failed to parse to VmIncomatibility. No Message in string".to_owned() })?;
-            let msg_id = msg_id.parse::<i64>().map_err(|e| HypervError { msg: format!("Failed to parse to VmIncomatibility. Cannot parse MessageId to i64: {}", e) })?;
+            let msg_id = parts.next().ok_or_else(|| HypervError::new("Failed to parse to VmIncomatibility. No MessageId in string".to_owned()))?;
+            let msg = parts.next().ok_or_else(|| HypervError::new("Failed to parse to VmIncomatibility. No Message in string".to_owned()))?;
+            let msg_id = msg_id.parse::<i64>().map_err(|e| HypervError::new(format!("Failed to parse to VmIncomatibility. Cannot parse MessageId to i64: {}", e)))?;
             Ok(Some(VmIncompatibility::from(msg_id, msg.to_owned())))
         })
     }
@@ -60,71 +225,108 @@ impl Hyperv {
             ImportType::Restore { vhd_path, virtual_machine_path } => {
                 match (vhd_path, virtual_machine_path) {
                     (None, None)  => "-Copy".to_owned(),
-                    (Some(vhdpath), None)  => format!("-Copy -VhdDestinationPath \"{}\"", vhdpath.to_string_lossy()),
-                    (None, Some(vmpath))  => format!("-Copy -VirtualMachinePath \"{}\"", vmpath.to_string_lossy()),
-                    (Some(vhdpath), Some(vmpath))  => format!("-Copy -VhdDestinationPath \"{}\" -VirtualMachinePath \"{}\"", vhdpath.to_string_lossy(), vmpath.to_string_lossy()),
+                    (Some(vhdpath), None)  => format!("-Copy -VhdDestinationPath {}", ps_quote(&vhdpath.to_string_lossy())),
+                    (None, Some(vmpath))  => format!("-Copy -VirtualMachinePath {}", ps_quote(&vmpath.to_string_lossy())),
+                    (Some(vhdpath), Some(vmpath))  => format!("-Copy -VhdDestinationPath {} -VirtualMachinePath {}", ps_quote(&vhdpath.to_string_lossy()), ps_quote(&vmpath.to_string_lossy())),
                 }
             },
             ImportType::Copy { vhd_path, virtual_machine_path } => {
                 match (vhd_path, virtual_machine_path) {
                     (None, None)  => "-GenerateNewId -Copy".to_owned(),
-                    (Some(vhdpath), None)  => format!("-GenerateNewId -Copy -VhdDestinationPath \"{}\"", vhdpath.to_string_lossy()),
-                    (None, Some(vmpath))  => format!("-GenerateNewId -Copy -VirtualMachinePath \"{}\"", vmpath.to_string_lossy()),
-                    (Some(vhdpath), Some(vmpath))  => format!("-GenerateNewId -Copy -VhdDestinationPath \"{}\" -VirtualMachinePath \"{}\"", vhdpath.to_string_lossy(), vmpath.to_string_lossy()),
+                    (Some(vhdpath), None)  => format!("-GenerateNewId -Copy -VhdDestinationPath {}", ps_quote(&vhdpath.to_string_lossy())),
+                    (None, Some(vmpath))  => format!("-GenerateNewId -Copy -VirtualMachinePath {}", ps_quote(&vmpath.to_string_lossy())),
+                    (Some(vhdpath), Some(vmpath))  => format!("-GenerateNewId -Copy -VhdDestinationPath {} -VirtualMachinePath {}", ps_quote(&vhdpath.to_string_lossy()), ps_quote(&vmpath.to_string_lossy())),
                 }
             }
         }
     }
 
     fn validate_file_path(path: &Path) -> Result<&str> {
-        if !path.is_file() {
+        // Paths on a remote host can only be checked there
+        if remote::is_local() && !path.is_file() {
             Err(HypervError::new("Path does not point to a valid file"))
         } else {
-            let path = path.to_str().ok_or_else(|| HypervError { msg: "Bad path".to_owned() })?;
+            let path = path.to_str().ok_or_else(|| HypervError::new("Bad path".to_owned()))?;
             Ok(path)
         }
     }
 
-    fn map_lines<T, F: Fn(&str) -> Result<Option<T>>>(stdout: Stdout, f: F) -> Result<Vec<T>> {
+    fn map_lines<T, F: Fn(&str) -> Result<Option<T>>>(output: &str, f: F) -> Result<Vec<T>> {
         let mut vec = Vec::new();
-        for line in BufReader::new(stdout).lines() {
-            match line {
-                Ok(line) => {
-                    if let Some(t) = f(&line)? {
-                        vec.push(t)
-                    }
-                }
-                Err(e) => Err(HypervError::new(format!("Failed to process powershell output. Could not split stdout into lines: {}", e)))?,
+        for line in output.lines() {
+            if let Some(t) = f(line)? {
+                vec.push(t)
             }
         }
 
         Ok(vec)
     }
 
+    pub(crate) fn spawn_and_deserialize<T: DeserializeOwned>(command: &str) -> Result<Option<T>> {
+        parse_output(&Self::spawn_and_read(command)?)
+    }
+
+    // For commands returning a collection. ConvertTo-Json emits a bare object rather than an array
+    // when there is exactly one item and nothing at all when there are none, so accept all three
+    pub(crate) fn spawn_and_deserialize_list<T: DeserializeOwned>(command: &str) -> Result<Vec<T>> {
+        parse_list_output(&Self::spawn_and_read(command)?)
+    }
+
+    pub(crate) fn spawn_and_read(command: &str) -> Result<String> {
+        // A session's process is shared and only returns output at the end, so commands that may have to
+        // be killed or report progress get one of their own
+        if operation::is_limited() {
+            return operation::run_killable(command)
+        }
+        if let Some(session) = session::current_session() {
+            let (prepared, _credential_scopes) = config::prepare_command(command);
+            if session::can_run(&prepared) {
+                let _slot = queue::acquire_slot();
+                return session.execute(&prepared)
+            }
+        }
+
+        Self::spawn_and_wait(command)
+    }
+
+    pub(crate) fn spawn_mutation_and_deserialize<T: DeserializeOwned>(command: &str) -> Result<Option<T>> {
+        Self::require_hyperv_admin_rights()?;
+        let result = Self::spawn_and_deserialize(command);
+        cache::note_mutation();
+        result
+    }
+
+    // Runs a command that changes host inventory. Every mutating operation must go through here
+    // so that inventory caches know their contents are stale
+    pub(crate) fn spawn_mutation(command: &str) -> Result<String> {
+        Self::require_hyperv_admin_rights()?;
+        let result = Self::spawn_and_read(command);
+        cache::note_mutation();
+        result
+    }
+
     fn spawn(command: &str) -> Result<PsProcess> {
-        PsCommand::new(command)
+        let (command, _credential_scope) = config::prepare_command(command);
+        PsCommand::new(&command)
             .stdout(Stdio::piped())
             .spawn()
             .map_err(|e| HypervError::new(format!("Failed to spawn PowerShell process: {}", e)))
     }
 
-    fn spawn_and_wait(command: &str) -> Result<Stdout> {
-        let mut process = Self::spawn(command)?;
-        let status = process.wait()
+    // Returns everything the command wrote to stdout. The output is drained while the process runs rather
+    // than after it exits: waiting first deadlocks once the output outgrows the pipe buffer, because
+    // PowerShell then blocks writing to a pipe nobody is reading
+    pub(crate) fn spawn_and_wait(command: &str) -> Result<String> {
+        let _slot = queue::acquire_slot();
+        let process = Self::spawn(command)?;
+        let output = process.wait_with_output()
             .map_err(|e| HypervError::new(format!("Failed while waiting for PowerShell process: {}", e)))?;
 
-        if !status.success() {
-            let exit_code_str = status.code().map(|c| c.to_string()).unwrap_or_else(|| "<none>".to_owned());
-            let output = process.wait_with_output() //.map(|c| c.to_string()).unwrap_or_else(|| "<none>".to_owned());
-                .map_err(|e| HypervError::new(format!("Failed while waiting for PowerShell process: {}", e)))?;
-            let stdout = to_string_truncated(&output.stdout, 1000);
-            let stderr = to_string_truncated(&output.stderr, 1000);
-            fn handle_blank(s: String) -> String { if !s.is_empty() { s } else { "<empty>".to_owned() } }
-            Err(HypervError { msg: format!("Powershell returned failure exit code: {}.\nStdout: {} \nStderr: {}", exit_code_str, handle_blank(stdout), handle_blank(stderr)) })
+        if !output.status.success() {
+            Err(process_failure(output.status.code(), &output.stdout, &output.stderr))
         } else {
-            let output = process.stdout()
-                .ok_or_else(|| HypervError::new("Failed obtain stdout of PowerShell process".to_owned()))?;
-            Ok(output)
+            String::from_utf8(output.stdout)
+                .map_err(|e| HypervError::new(format!("Failed to read powershell output: {}", e)))
         }
     }
 }
@@ -135,16 +337,178 @@ pub enum ImportType<'a, 'b> {
     Copy { vhd_path: Option<&'a Path>, virtual_machine_path: Option<&'b Path> },
 }
 
-#[derive(Debug, Deserialize)]
+// How an imported VM should be adapted to this host
+#[derive(Debug, Clone, Default)]
+pub struct ImportRemap {
+    name: Option<String>,
+    switches: Vec<(String, String)>,
+}
+
+impl ImportRemap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rename<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    // Adapters connected to a switch named `from` on the exporting host get connected to `to` instead
+    pub fn reconnect<S: Into<String>, T: Into<String>>(mut self, from: S, to: T) -> Self {
+        self.switches.push((from.into(), to.into()));
+        self
+    }
+}
+
+// get_vms(), get_vm_by_id() and get_vm_by_name() fill in every property. Queries taking a VmProp list,
+// like get_vms_with(), leave those not asked for as None
+#[derive(Debug, Clone, Deserialize)]
 pub struct Vm {
     #[serde(rename = "Id")]
     pub id: VmId,
     #[serde(rename = "Name")]
     pub name: String,
+    #[serde(rename = "State", default)]
+    pub state: Option<VmState>,
+    #[serde(rename = "Status", default)]
+    pub status: Option<String>,
+    #[serde(rename = "Uptime", default, deserialize_with = "convert::deserialize_timespan")]
+    pub uptime: Option<Duration>,
+    #[serde(rename = "Generation", default)]
+    pub generation: Option<u8>,
+    #[serde(rename = "ProcessorCount", default)]
+    pub processor_count: Option<u32>,
+    #[serde(rename = "MemoryAssigned", default)]
+    pub memory_assigned: Option<u64>,
+    #[serde(rename = "Version", default)]
+    pub version: Option<String>,
 }
 
-// TODO: should this be a newtype?
-pub type VmId = Uuid;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VmProp {
+    State,
+    Status,
+    Uptime,
+    Generation,
+    ProcessorCount,
+    MemoryAssigned,
+    Version,
+}
+
+impl VmProp {
+    pub const ALL: [VmProp; 7] = [
+        VmProp::State,
+        VmProp::Status,
+        VmProp::Uptime,
+        VmProp::Generation,
+        VmProp::ProcessorCount,
+        VmProp::MemoryAssigned,
+        VmProp::Version,
+    ];
+
+    fn projection_of(props: &[VmProp]) -> String {
+        let mut projection = vec!["Id", "Name"];
+        for prop in props {
+            let expr = prop.projection();
+            if !projection.contains(&expr) {
+                projection.push(expr);
+            }
+        }
+        projection.join(",")
+    }
+
+    // Enums and TimeSpans are flattened in PowerShell so that ConvertTo-Json emits plain strings and numbers for them
+    fn projection(&self) -> &'static str {
+        match self {
+            VmProp::State => "@{Name='State';Expression={$_.State.ToString()}}",
+            VmProp::Status => "Status",
+            VmProp::Uptime => "@{Name='Uptime';Expression={[int64]$_.Uptime.TotalSeconds}}",
+            VmProp::Generation => "Generation",
+            VmProp::ProcessorCount => "ProcessorCount",
+            VmProp::MemoryAssigned => "MemoryAssigned",
+            VmProp::Version => "Version",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmState {
+    Running,
+    Off,
+    Starting,
+    Stopping,
+    Saved,
+    Saving,
+    Paused,
+    Pausing,
+    Resuming,
+    Reset,
+    Other(String),
+}
+
+impl From<&str> for VmState {
+    fn from(s: &str) -> Self {
+        match s {
+            "Running" => VmState::Running,
+            "Off" => VmState::Off,
+            "Starting" => VmState::Starting,
+            "Stopping" => VmState::Stopping,
+            "Saved" => VmState::Saved,
+            "Saving" => VmState::Saving,
+            "Paused" => VmState::Paused,
+            "Pausing" => VmState::Pausing,
+            "Resuming" => VmState::Resuming,
+            "Reset" => VmState::Reset,
+            s => VmState::Other(s.to_owned()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for VmState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Ok(VmState::from(s.as_str()))
+    }
+}
+
+// Kept distinct from Uuid so a VM's Id can't be mixed up with the Id of a checkpoint, switch or CPU group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VmId(Uuid);
+
+impl VmId {
+    // Accepts the forms Hyper-V prints, with or without hyphens or braces and in either case
+    pub fn parse(s: &str) -> Result<Self> {
+        Uuid::parse_str(s.trim().trim_start_matches('{').trim_end_matches('}'))
+            .map(VmId)
+            .map_err(|e| HypervError::new(format!("Invalid VM id '{}': {}", s, e)))
+    }
+
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        VmId(uuid)
+    }
+
+    pub fn as_uuid(&self) -> &Uuid {
+        &self.0
+    }
+}
+
+impl fmt::Display for VmId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for VmId {
+    type Err = HypervError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+const MISSING_SWITCH_MESSAGE_ID: i64 = 33012;
 
 #[derive(Debug)]
 pub enum VmIncompatibility {
@@ -165,7 +529,7 @@ impl VmIncompatibility {
             16350 => VmIncompatibility::CannotChangeCheckpointLocation(msg),
             16352 => VmIncompatibility::CannotChangeSmartPagingStore(msg),
             25014 => VmIncompatibility::CannotRestoreSavedState(msg),
-            33012 => VmIncompatibility::MissingSwitch(msg),
+            MISSING_SWITCH_MESSAGE_ID => VmIncompatibility::MissingSwitch(msg),
             msg_id => VmIncompatibility::Other(msg, msg_id)
         }
     }
@@ -177,7 +541,7 @@ impl VmIncompatibility {
             VmIncompatibility::CannotChangeCheckpointLocation(_) => 16350,
             VmIncompatibility::CannotChangeSmartPagingStore(_) => 16352,
             VmIncompatibility::CannotRestoreSavedState(_) => 25014,
-            VmIncompatibility::MissingSwitch(_) => 33012,
+            VmIncompatibility::MissingSwitch(_) => MISSING_SWITCH_MESSAGE_ID,
             VmIncompatibility::Other(_, i) => *i,
         }
     }
@@ -199,11 +563,55 @@ impl VmIncompatibility {
 #[derive(Debug, Fail)]
 pub struct HypervError  {
     pub msg: String,
+    pub kind: ErrorKind,
+    // Boxed so that Result<T> stays small. Most errors have no record
+    record: Option<Box<ErrorRecord>>,
+}
+
+// Failures callers may want to handle programmatically. Everything else is Other
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    // More than one VM has the given name. Carries the Ids of all of them
+    AmbiguousName(Vec<VmId>),
+    // The process lacks the rights for the operation. Tells whether adding the account to the
+    // Hyper-V Administrators group would be enough or whether it must run elevated
+    InsufficientPrivileges { hyperv_administrators_suffices: bool },
+    // The operation is not possible for this VM in its current state, generation or configuration version
+    NotSupported,
+    // The VM is in a state the requested transition cannot start from. Carries that state
+    InvalidState(VmState),
+    // The operation did not complete within the time the caller allowed
+    Timeout,
+    // The caller cancelled the operation through its Operation handle
+    Cancelled,
+    // A cmdlet failed because the VM, disk, switch or other object it was given does not exist
+    NotFound,
+    // A cmdlet was refused access, e.g. to a file or a remote host. Failed privilege checks made
+    // before an operation starts are InsufficientPrivileges instead
+    PermissionDenied,
+    Other,
 }
 
 impl HypervError {
-    fn new<T: Into<String>>(msg: T) -> Self {
-        Self { msg: msg.into() }
+    pub(crate) fn new<T: Into<String>>(msg: T) -> Self {
+        Self { msg: msg.into(), kind: ErrorKind::Other, record: None }
+    }
+
+    pub(crate) fn with_kind<T: Into<String>>(kind: ErrorKind, msg: T) -> Self {
+        Self { msg: msg.into(), kind, record: None }
+    }
+
+    pub(crate) fn from_record(record: ErrorRecord) -> Self {
+        Self { msg: format!("{} ({})", record.message, record.fully_qualified_error_id), kind: record.kind(), record: Some(Box::new(record)) }
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    // The PowerShell error record behind the failure, if it came from a failing cmdlet
+    pub fn error_record(&self) -> Option<&ErrorRecord> {
+        self.record.as_deref()
     }
 }
 
@@ -213,7 +621,106 @@ impl fmt::Display for HypervError {
     }
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    Many(Vec<T>),
+    One(T),
+}
+
+fn parse_output<T: DeserializeOwned>(output: &str) -> Result<Option<T>> {
+    if output.trim().is_empty() {
+        return Ok(None)
+    }
+
+    serde_json::from_str(output)
+        .map_err(|e| HypervError::new(format!("Failed to parse powershell output: {}", e)))
+}
+
+fn parse_list_output<T: DeserializeOwned>(output: &str) -> Result<Vec<T>> {
+    let items: Option<OneOrMany<T>> = parse_output(output)?;
+    Ok(match items {
+        Some(OneOrMany::Many(items)) => items,
+        Some(OneOrMany::One(item)) => vec![item],
+        None => Vec::new(),
+    })
+}
+
+fn vm_not_found(id: &VmId) -> HypervError {
+    HypervError::with_kind(ErrorKind::NotFound, format!("No VM with Id {} exists", id))
+}
+
+// The error for a PowerShell process that exited with a failure code
+pub(crate) fn process_failure(exit_code: Option<i32>, stdout: &[u8], stderr: &[u8]) -> HypervError {
+    if let Some(record) = error_record::parse(&String::from_utf8_lossy(stderr)) {
+        return HypervError::from_record(record)
+    }
+    let exit_code_str = exit_code.map(|c| c.to_string()).unwrap_or_else(|| "<none>".to_owned());
+    let stdout = to_string_truncated(stdout, 1000);
+    let stderr = to_string_truncated(stderr, 1000);
+    fn handle_blank(s: String) -> String { if !s.is_empty() { s } else { "<empty>".to_owned() } }
+    HypervError::new(format!("Powershell returned failure exit code: {}.\nStdout: {} \nStderr: {}", exit_code_str, handle_blank(stdout), handle_blank(stderr)))
+}
+
 fn to_string_truncated(bytes: &[u8], take: usize) -> String {
     let len = std::cmp::min(bytes.len(), take);
     String::from_utf8_lossy(&bytes[..len]).to_string()
-}
\ No newline at end of file
+}
+
+// Wraps a value in a PowerShell single quoted string literal so it is never interpreted by the shell
+pub(crate) fn ps_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "6f4a4c2e-9d2b-4a0c-8a43-1f2e3d4c5b6a";
+
+    #[test]
+    fn list_output_accepts_single_object() {
+        let output = format!("{{ \"Id\": \"{}\", \"Name\": \"vm1\" }}", ID);
+        let vms: Vec<Vm> = parse_list_output(&output).unwrap();
+        assert_eq!(vms.len(), 1);
+        assert_eq!(vms[0].name, "vm1");
+    }
+
+    #[test]
+    fn list_output_accepts_array() {
+        let output = format!("[{{ \"Id\": \"{0}\", \"Name\": \"vm1\" }}, {{ \"Id\": \"{0}\", \"Name\": \"vm2\" }}]", ID);
+        let vms: Vec<Vm> = parse_list_output(&output).unwrap();
+        assert_eq!(vms.iter().map(|vm| vm.name.as_str()).collect::<Vec<_>>(), vec!["vm1", "vm2"]);
+    }
+
+    #[test]
+    fn list_output_accepts_empty_and_null() {
+        assert!(parse_list_output::<Vm>("").unwrap().is_empty());
+        assert!(parse_list_output::<Vm>("  \r\n").unwrap().is_empty());
+        assert!(parse_list_output::<Vm>("null").unwrap().is_empty());
+        assert!(parse_list_output::<Vm>("[]").unwrap().is_empty());
+    }
+
+    #[test]
+    fn vm_tolerates_missing_null_and_extra_properties() {
+        let output = format!("{{ \"Id\": \"{}\", \"Name\": \"vm1\", \"State\": null, \"Uptime\": 90, \"Unknown\": {{ \"Nested\": 1 }} }}", ID);
+        let vm: Vm = parse_output(&output).unwrap().unwrap();
+        assert_eq!(vm.state, None);
+        assert_eq!(vm.uptime, Some(Duration::from_secs(90)));
+        assert_eq!(vm.generation, None);
+    }
+
+    #[test]
+    fn vm_id_parses_hyperv_forms() {
+        let id = VmId::parse(ID).unwrap();
+        assert_eq!(id.to_string(), ID);
+        assert_eq!(VmId::parse(&format!("{{{}}}", ID.to_uppercase())).unwrap(), id);
+        assert_eq!(serde_json::to_string(&id).unwrap(), format!("\"{}\"", ID));
+        assert!("not-an-id".parse::<VmId>().is_err());
+    }
+
+    #[test]
+    fn malformed_output_is_an_error() {
+        assert!(parse_list_output::<Vm>("{ \"Name\": ").is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{ErrorKind, Hyperv, HypervError, Result, VmId, VmProp, VmRef, VmState};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMode {
+    // Asks the guest to shut down through the shutdown integration service. Fails instead of
+    // forcing the shutdown if the guest is locked or applications have unsaved data
+    Shutdown,
+    // Shuts the guest down even if it is locked. Applications with unsaved data get five minutes
+    Force,
+    // Cuts the power without involving the guest
+    TurnOff,
+    // Saves the VM's memory to disk so it resumes where it left off
+    Save,
+}
+
+impl StopMode {
+    fn params(&self) -> &'static str {
+        match self {
+            StopMode::Shutdown => "",
+            StopMode::Force => "-Force",
+            StopMode::TurnOff => "-TurnOff -Force",
+            StopMode::Save => "-Save",
+        }
+    }
+}
+
+impl Hyperv {
+    pub fn start_vm<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<()> {
+        let id = vm.into().resolve()?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | start-vm -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Waits at most timeout for the VM to stop, if given, and fails with ErrorKind::Timeout after that.
+    // The guest may still go on to stop by itself after a timeout
+    pub fn stop_vm<'a, V: Into<VmRef<'a>>>(vm: V, mode: StopMode, timeout: Option<Duration>) -> Result<()> {
+        let id = vm.into().resolve()?;
+        let command = match timeout {
+            Some(timeout) => format!(
+                "$job = get-vm -Id '{}' -ErrorAction Stop | stop-vm {} -AsJob -ErrorAction Stop;
+                if (wait-job -Job $job -Timeout {}) {{ receive-job -Job $job -ErrorAction Stop }}
+                else {{ stop-job -Job $job; 'TimedOut' | convertto-json }}",
+                id,
+                mode.params(),
+                timeout.as_secs().max(1)),
+            None => format!("get-vm -Id '{}' -ErrorAction Stop | stop-vm {} -ErrorAction Stop", id, mode.params()),
+        };
+
+        let timed_out: Option<String> = Self::spawn_mutation_and_deserialize(&command)?;
+        if timed_out.is_some() {
+            return Err(HypervError::with_kind(ErrorKind::Timeout, format!("VM {} did not stop within {:?}", id, timeout.unwrap_or_default())))
+        }
+        Ok(())
+    }
+
+    // Resets the VM like the reset button of a physical machine. The guest is not asked to shut down first
+    pub fn restart_vm<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<()> {
+        let id = vm.into().resolve()?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | restart-vm -Force -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Deletes the VM. With delete_disks its hard disk files are deleted too, along with the checkpoint
+    // disks (.avhdx) they sit on. Parents shared with other VMs, like a golden image a differencing disk
+    // was created from, are left alone
+    pub fn remove_vm<'a, V: Into<VmRef<'a>>>(vm: V, delete_disks: bool) -> Result<()> {
+        let id = vm.into().resolve()?;
+        let delete = if delete_disks {
+            "foreach ($disk in $disks) { remove-item -LiteralPath $disk -Force -ErrorAction Stop }"
+        } else {
+            ""
+        };
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop;
+            $disks = @($vm | get-vmharddiskdrive | where-object {{ $_.Path }} | foreach-object {{
+                $path = $_.Path;
+                $path;
+                while ($path -match '\\.avhdx?$') {{
+                    $path = (get-vhd -Path $path -ErrorAction Stop).ParentPath;
+                    if ($path) {{ $path }}
+                }}
+            }});
+            $vm | remove-vm -Force -ErrorAction Stop;
+            {}",
+            id,
+            delete);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Freezes a running VM in memory
+    pub fn suspend_vm<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<()> {
+        let id = vm.into().resolve()?;
+        Self::require_state(&id, &[VmState::Running], "suspended")?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | suspend-vm -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    pub fn resume_vm<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<()> {
+        let id = vm.into().resolve()?;
+        Self::require_state(&id, &[VmState::Paused], "resumed")?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | resume-vm -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Writes the memory of a running or paused VM to disk and stops it
+    pub fn save_vm<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<()> {
+        let id = vm.into().resolve()?;
+        Self::require_state(&id, &[VmState::Running, VmState::Paused], "saved")?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | save-vm -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Starts a saved VM from its saved state
+    pub fn restore_vm<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<()> {
+        let id = vm.into().resolve()?;
+        Self::require_state(&id, &[VmState::Saved], "restored")?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | start-vm -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    fn require_state(id: &VmId, allowed: &[VmState], transition: &str) -> Result<()> {
+        let state = Self::get_vm_with(id, &[VmProp::State])?.state
+            .ok_or_else(|| HypervError::new(format!("Get-VM returned no state for VM {}", id)))?;
+        if !allowed.contains(&state) {
+            return Err(HypervError::with_kind(ErrorKind::InvalidState(state.clone()), format!("VM {} cannot be {} while in state {:?}", id, transition, state)))
+        }
+        Ok(())
+    }
+}
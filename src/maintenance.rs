@@ -0,0 +1,136 @@
+use crate::{ErrorKind, Hyperv, HypervError, Result, StopMode, VmId, VmProp, VmState};
+use crate::{operation, queue, remote, session};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(300);
+const DEFAULT_MAX_PARALLEL: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuiesceAction {
+    Save,
+    // Asks the guest to shut down through the shutdown integration service
+    Shutdown,
+    // Leaves the VM running
+    Skip,
+}
+
+// What quiesce_host() does to each running VM. VMs without an override get the default action
+pub struct QuiescePolicy {
+    default_action: QuiesceAction,
+    overrides: HashMap<VmId, QuiesceAction>,
+    shutdown_timeout: Duration,
+    turn_off_on_timeout: bool,
+    max_parallel: usize,
+}
+
+impl QuiescePolicy {
+    pub fn new(default_action: QuiesceAction) -> Self {
+        Self {
+            default_action,
+            overrides: HashMap::new(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            turn_off_on_timeout: false,
+            max_parallel: DEFAULT_MAX_PARALLEL,
+        }
+    }
+
+    pub fn action_for(mut self, id: VmId, action: QuiesceAction) -> Self {
+        self.overrides.insert(id, action);
+        self
+    }
+
+    // How long a guest gets to shut down. Defaults to 5 minutes
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    // Turns off guests that do not shut down in time instead of reporting them as failed
+    pub fn turn_off_on_timeout(mut self, turn_off: bool) -> Self {
+        self.turn_off_on_timeout = turn_off;
+        self
+    }
+
+    // How many VMs are quiesced at the same time. Defaults to 4
+    pub fn max_parallel(mut self, max: usize) -> Self {
+        self.max_parallel = max;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum QuiesceOutcome {
+    Saved,
+    ShutDown,
+    // The guest did not shut down in time and was powered off
+    TurnedOff,
+    Skipped,
+    Failed(HypervError),
+}
+
+#[derive(Debug)]
+pub struct QuiescedVm {
+    pub id: VmId,
+    pub name: String,
+    pub outcome: QuiesceOutcome,
+}
+
+impl Hyperv {
+    // Saves or shuts down every running VM as the policy says, e.g. before patching or rebooting the host.
+    // Failures do not stop the remaining VMs from being quiesced; they are listed in the returned report
+    pub fn quiesce_host(policy: &QuiescePolicy) -> Result<Vec<QuiescedVm>> {
+        if policy.max_parallel == 0 {
+            return Err(HypervError::new("At least one VM must be quiesced at a time"))
+        }
+
+        let running: Vec<_> = Self::get_vms_with(&[VmProp::State])?
+            .into_iter()
+            .filter(|vm| vm.state == Some(VmState::Running))
+            .collect();
+
+        let pending = Mutex::new(running.into_iter());
+        let report = Mutex::new(Vec::new());
+        // Workers run with the caller's host, operation, priority and session, as the caller's own thread would
+        let host = remote::current_host();
+        let operation = operation::current_operation();
+        let priority = queue::current_priority();
+        let session = session::current_session();
+        thread::scope(|scope| {
+            for _ in 0..policy.max_parallel {
+                scope.spawn(|| remote::with_host(host.as_ref(), || operation::with_operation(operation.as_ref(), ||
+                    Self::with_priority(priority, || session::with_session(session.as_ref(), || loop {
+                        let vm = match pending.lock().unwrap_or_else(|e| e.into_inner()).next() {
+                            Some(vm) => vm,
+                            None => break,
+                        };
+                        let action = policy.overrides.get(&vm.id).copied().unwrap_or(policy.default_action);
+                        let outcome = Self::quiesce_vm(&vm.id, action, policy).unwrap_or_else(QuiesceOutcome::Failed);
+                        report.lock().unwrap_or_else(|e| e.into_inner()).push(QuiescedVm { id: vm.id, name: vm.name, outcome });
+                    })))));
+            }
+        });
+
+        Ok(report.into_inner().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn quiesce_vm(id: &VmId, action: QuiesceAction, policy: &QuiescePolicy) -> Result<QuiesceOutcome> {
+        match action {
+            QuiesceAction::Skip => Ok(QuiesceOutcome::Skipped),
+            QuiesceAction::Save => {
+                Self::stop_vm(id, StopMode::Save, None)?;
+                Ok(QuiesceOutcome::Saved)
+            },
+            QuiesceAction::Shutdown => match Self::stop_vm(id, StopMode::Force, Some(policy.shutdown_timeout)) {
+                Ok(()) => Ok(QuiesceOutcome::ShutDown),
+                Err(ref e) if e.kind == ErrorKind::Timeout && policy.turn_off_on_timeout => {
+                    Self::stop_vm(id, StopMode::TurnOff, None)?;
+                    Ok(QuiesceOutcome::TurnedOff)
+                },
+                Err(e) => Err(e),
+            },
+        }
+    }
+}
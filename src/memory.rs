@@ -0,0 +1,94 @@
+use crate::{ErrorKind, Hyperv, HypervError, Result, VmId, VmState};
+use serde_derive::Deserialize;
+
+const MAX_MEMORY_PRIORITY: u32 = 100;
+const MEMORY_ALIGNMENT: u64 = 2 * 1024 * 1024;
+// Runtime memory resize needs configuration version 7.0 (Windows Server 2016) or later
+const MIN_RUNTIME_RESIZE_VERSION: (u32, u32) = (7, 0);
+
+// Sizes are in bytes
+#[derive(Debug, Clone, Deserialize)]
+pub struct MemorySettings {
+    #[serde(rename = "Startup")]
+    pub startup: u64,
+    #[serde(rename = "Minimum")]
+    pub minimum: u64,
+    #[serde(rename = "Maximum")]
+    pub maximum: u64,
+    #[serde(rename = "DynamicMemoryEnabled")]
+    pub dynamic_memory_enabled: bool,
+    #[serde(rename = "Priority")]
+    pub priority: u32,
+    // Percentage of extra memory dynamic memory tries to keep assigned
+    #[serde(rename = "Buffer")]
+    pub buffer: u32,
+}
+
+pub(crate) const MEMORY_PROJECTION: &str = "Startup,Minimum,Maximum,DynamicMemoryEnabled,Priority,Buffer";
+
+#[derive(Deserialize)]
+struct MemoryConfig {
+    #[serde(rename = "State")]
+    state: VmState,
+    #[serde(rename = "Version")]
+    version: String,
+    #[serde(rename = "DynamicMemoryEnabled")]
+    dynamic_memory_enabled: bool,
+}
+
+impl Hyperv {
+    // When the host runs short of memory, VMs with a higher priority (0-100) are given memory first
+    pub fn set_memory_priority(id: &VmId, weight: u32) -> Result<()> {
+        if weight > MAX_MEMORY_PRIORITY {
+            return Err(HypervError::new(format!("Invalid memory priority {}. Must be between 0 and {}", weight, MAX_MEMORY_PRIORITY)))
+        }
+
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | set-vmmemory -Priority {} -ErrorAction Stop", id, weight);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    pub fn get_memory_priority(id: &VmId) -> Result<u32> {
+        let command = format!("(get-vm -Id '{}' -ErrorAction Stop | get-vmmemory).Priority | convertto-json", id);
+        let priority: Option<u32> = Self::spawn_and_deserialize(&command)?;
+        priority.ok_or_else(|| HypervError::new("Get-VMMemory returned no priority"))
+    }
+
+    // Sets the VM's memory. Running VMs are resized in place where Hyper-V allows it. Otherwise an
+    // ErrorKind::NotSupported error explains why the VM has to be turned off first. Hyper-V does not
+    // change the memory of a saved VM at all, so that fails with ErrorKind::InvalidState
+    pub fn set_memory(id: &VmId, bytes: u64) -> Result<()> {
+        if bytes == 0 || !bytes.is_multiple_of(MEMORY_ALIGNMENT) {
+            return Err(HypervError::new(format!("Invalid memory size {}. Must be a non-zero multiple of 2 MB", bytes)))
+        }
+
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | select-object -property @{{Name='State';Expression={{$_.State.ToString()}}}},Version,DynamicMemoryEnabled | convertto-json",
+            id);
+        let config: Option<MemoryConfig> = Self::spawn_and_deserialize(&command)?;
+        let config = config.ok_or_else(|| HypervError::new("VM memory query returned no output"))?;
+
+        if config.state == VmState::Saved {
+            return Err(HypervError::with_kind(ErrorKind::InvalidState(config.state), "The memory of a saved VM cannot be changed. Start it or turn it off first"))
+        }
+        if config.state != VmState::Off {
+            if config.dynamic_memory_enabled {
+                return Err(HypervError::with_kind(ErrorKind::NotSupported, "Startup memory of a running VM with dynamic memory cannot be changed. Change its minimum and maximum instead"))
+            }
+            if parse_version(&config.version).is_none_or(|v| v < MIN_RUNTIME_RESIZE_VERSION) {
+                return Err(HypervError::with_kind(ErrorKind::NotSupported, format!("VM configuration version {} does not support resizing memory while running. Version 7.0 or later is required", config.version)))
+            }
+        }
+
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | set-vmmemory -StartupBytes {} -ErrorAction Stop", id, bytes);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
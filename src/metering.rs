@@ -0,0 +1,94 @@
+use crate::{Hyperv, HypervError, Result, VmRef};
+use serde_derive::Deserialize;
+use std::time::Duration;
+
+// Usage a VM accumulated since resource metering was enabled or last reset. Memory, disk and network
+// figures are in MB as Hyper-V reports them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmMetrics {
+    pub avg_cpu_mhz: u64,
+    pub avg_memory_mb: u64,
+    pub min_memory_mb: u64,
+    pub max_memory_mb: u64,
+    // Space allocated to the VM's disks, not what the guest has written to them
+    pub total_disk_mb: u64,
+    pub network_inbound_mb: u64,
+    pub network_outbound_mb: u64,
+    pub metering_duration: Duration,
+}
+
+#[derive(Deserialize)]
+struct RawVmMetrics {
+    #[serde(rename = "AvgCPU")]
+    avg_cpu_mhz: u64,
+    #[serde(rename = "AvgRAM")]
+    avg_memory_mb: u64,
+    #[serde(rename = "MinRAM")]
+    min_memory_mb: u64,
+    #[serde(rename = "MaxRAM")]
+    max_memory_mb: u64,
+    #[serde(rename = "TotalDisk")]
+    total_disk_mb: u64,
+    #[serde(rename = "NetworkInbound")]
+    network_inbound_mb: u64,
+    #[serde(rename = "NetworkOutbound")]
+    network_outbound_mb: u64,
+    #[serde(rename = "MeteringDurationMs")]
+    metering_duration_ms: u64,
+}
+
+impl Hyperv {
+    // Metering survives VM restarts and keeps counting until disabled or reset
+    pub fn enable_resource_metering<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<()> {
+        let id = vm.into().resolve()?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | enable-vmresourcemetering -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    pub fn disable_resource_metering<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<()> {
+        let id = vm.into().resolve()?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | disable-vmresourcemetering -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Fails if metering is not enabled for the VM. Figures Hyper-V has not collected yet are reported as 0
+    pub fn measure_vm<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<VmMetrics> {
+        let id = vm.into().resolve()?;
+        let command = format!(
+            "$report = get-vm -Id '{}' -ErrorAction Stop | measure-vm -ErrorAction Stop;
+            $traffic = @($report.NetworkMeteredTrafficReport);
+            [pscustomobject]@{{
+                AvgCPU = [uint64]$report.AvgCPU;
+                AvgRAM = [uint64]$report.AvgRAM;
+                MinRAM = [uint64]$report.MinRAM;
+                MaxRAM = [uint64]$report.MaxRAM;
+                TotalDisk = [uint64]$report.TotalDisk;
+                NetworkInbound = [uint64]($traffic | where-object {{ $_.Direction.ToString() -eq 'Inbound' }} | measure-object -Property TotalTraffic -Sum).Sum;
+                NetworkOutbound = [uint64]($traffic | where-object {{ $_.Direction.ToString() -eq 'Outbound' }} | measure-object -Property TotalTraffic -Sum).Sum;
+                MeteringDurationMs = [uint64]$report.MeteringDuration.TotalMilliseconds
+            }} | convertto-json",
+            id);
+        let raw: Option<RawVmMetrics> = Self::spawn_and_deserialize(&command)?;
+        let raw = raw.ok_or_else(|| HypervError::new("Measure-VM returned no output"))?;
+        Ok(VmMetrics {
+            avg_cpu_mhz: raw.avg_cpu_mhz,
+            avg_memory_mb: raw.avg_memory_mb,
+            min_memory_mb: raw.min_memory_mb,
+            max_memory_mb: raw.max_memory_mb,
+            total_disk_mb: raw.total_disk_mb,
+            network_inbound_mb: raw.network_inbound_mb,
+            network_outbound_mb: raw.network_outbound_mb,
+            metering_duration: Duration::from_millis(raw.metering_duration_ms),
+        })
+    }
+
+    // Starts a new metering period, e.g. after a chargeback run has read the current figures
+    pub fn reset_resource_metering<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<()> {
+        let id = vm.into().resolve()?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | reset-vmresourcemetering -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
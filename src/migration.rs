@@ -0,0 +1,183 @@
+use crate::{Hyperv, HypervError, Result, VmRef, ps_quote};
+use std::path::{Path, PathBuf};
+
+// How move_vm() treats the VM's storage. By default it stays where it is, which only works if the
+// destination host can reach it, e.g. on an SMB share
+pub struct MoveVmOptions {
+    destination_storage_path: Option<PathBuf>,
+    retain_vhd_copies_on_source: bool,
+}
+
+impl MoveVmOptions {
+    pub fn new() -> Self {
+        Self { destination_storage_path: None, retain_vhd_copies_on_source: false }
+    }
+
+    // Copies the VM's disks, checkpoints and configuration to this directory on the destination host
+    pub fn include_storage<P: AsRef<Path>>(mut self, destination_path: P) -> Self {
+        self.destination_storage_path = Some(destination_path.as_ref().to_owned());
+        self
+    }
+
+    // Leaves the source host's copies of the disks in place after moving them. Needs include_storage()
+    pub fn retain_vhd_copies_on_source(mut self, retain: bool) -> Self {
+        self.retain_vhd_copies_on_source = retain;
+        self
+    }
+
+    fn params(&self) -> Result<String> {
+        let mut params = Vec::new();
+        if let Some(path) = &self.destination_storage_path {
+            params.push(format!("-IncludeStorage -DestinationStoragePath {}", ps_quote(path_str(path)?)));
+        }
+        if self.retain_vhd_copies_on_source {
+            if self.destination_storage_path.is_none() {
+                return Err(HypervError::new("VHD copies can only be retained on the source when the storage is moved too"))
+            }
+            params.push("-RetainVhdCopiesOnSource".to_owned());
+        }
+        Ok(params.join(" "))
+    }
+}
+
+impl Default for MoveVmOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Where move_vm_storage() puts the VM's files. Either everything goes into one directory with all_in(),
+// or each kind of file is placed separately. Files without a destination stay where they are
+pub struct StoragePaths {
+    all_in: Option<PathBuf>,
+    virtual_machine_path: Option<PathBuf>,
+    snapshot_file_path: Option<PathBuf>,
+    smart_paging_file_path: Option<PathBuf>,
+    vhds: Vec<(PathBuf, PathBuf)>,
+}
+
+impl StoragePaths {
+    pub fn new() -> Self {
+        Self { all_in: None, virtual_machine_path: None, snapshot_file_path: None, smart_paging_file_path: None, vhds: Vec::new() }
+    }
+
+    pub fn all_in<P: AsRef<Path>>(path: P) -> Self {
+        Self { all_in: Some(path.as_ref().to_owned()), ..Self::new() }
+    }
+
+    // Directory of the VM's configuration
+    pub fn virtual_machine_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.virtual_machine_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn snapshot_file_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.snapshot_file_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn smart_paging_file_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.smart_paging_file_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    // Moves one of the VM's disks to the given file
+    pub fn vhd<S: AsRef<Path>, D: AsRef<Path>>(mut self, source: S, destination: D) -> Self {
+        self.vhds.push((source.as_ref().to_owned(), destination.as_ref().to_owned()));
+        self
+    }
+
+    fn params(&self) -> Result<String> {
+        let has_separate_paths = self.virtual_machine_path.is_some()
+            || self.snapshot_file_path.is_some()
+            || self.smart_paging_file_path.is_some()
+            || !self.vhds.is_empty();
+
+        if let Some(path) = &self.all_in {
+            if has_separate_paths {
+                return Err(HypervError::new("Storage moved all into one directory cannot also have separate destinations"))
+            }
+            return Ok(format!("-DestinationStoragePath {}", ps_quote(path_str(path)?)))
+        }
+        if !has_separate_paths {
+            return Err(HypervError::new("No destination given for any of the VM's files"))
+        }
+
+        let mut params = Vec::new();
+        if let Some(path) = &self.virtual_machine_path {
+            params.push(format!("-VirtualMachinePath {}", ps_quote(path_str(path)?)));
+        }
+        if let Some(path) = &self.snapshot_file_path {
+            params.push(format!("-SnapshotFilePath {}", ps_quote(path_str(path)?)));
+        }
+        if let Some(path) = &self.smart_paging_file_path {
+            params.push(format!("-SmartPagingFilePath {}", ps_quote(path_str(path)?)));
+        }
+        if !self.vhds.is_empty() {
+            let vhds = self.vhds.iter()
+                .map(|(source, destination)| Ok(format!(
+                    "@{{SourceFilePath = {}; DestinationFilePath = {}}}",
+                    ps_quote(path_str(source)?),
+                    ps_quote(path_str(destination)?))))
+                .collect::<Result<Vec<_>>>()?;
+            params.push(format!("-Vhds @({})", vhds.join(",")));
+        }
+        Ok(params.join(" "))
+    }
+}
+
+impl Default for StoragePaths {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hyperv {
+    // Moves the VM to another Hyper-V host, live if it is running. Both hosts must have live migration
+    // enabled and, unless CredSSP is configured, Kerberos constrained delegation set up between them.
+    // Returns once the move has finished
+    pub fn move_vm<'a, V: Into<VmRef<'a>>>(vm: V, destination_host: &str, options: &MoveVmOptions) -> Result<()> {
+        if destination_host.trim().is_empty() {
+            return Err(HypervError::new("Destination host cannot be empty"))
+        }
+        let id = vm.into().resolve()?;
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | move-vm -DestinationHost {} {} -ErrorAction Stop",
+            id,
+            ps_quote(destination_host),
+            options.params()?);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Relocates the VM's files, e.g. to a new volume, without moving the VM to another host. Running VMs
+    // keep running while their files are copied. The old files are removed once the move has finished
+    pub fn move_vm_storage<'a, V: Into<VmRef<'a>>>(vm: V, new_paths: &StoragePaths) -> Result<()> {
+        let id = vm.into().resolve()?;
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | move-vmstorage {} -ErrorAction Stop",
+            id,
+            new_paths.params()?);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| HypervError::new("Bad path"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storage_paths_params() {
+        assert_eq!(StoragePaths::all_in("D:\\vms").params().unwrap(), "-DestinationStoragePath 'D:\\vms'");
+        assert_eq!(
+            StoragePaths::new().vhd("C:\\a.vhdx", "D:\\a.vhdx").params().unwrap(),
+            "-Vhds @(@{SourceFilePath = 'C:\\a.vhdx'; DestinationFilePath = 'D:\\a.vhdx'})");
+        assert!(StoragePaths::all_in("D:\\vms").snapshot_file_path("E:\\snapshots").params().is_err());
+        assert!(StoragePaths::new().params().is_err());
+    }
+}
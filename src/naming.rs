@@ -0,0 +1,164 @@
+use crate::{ErrorKind, Hyperv, HypervError, Result, Vm, VmId, VmProp, ps_quote};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+
+// Identifies a VM either by Id or by name. A name must belong to exactly one VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmRef<'a> {
+    Id(VmId),
+    Name(&'a str),
+}
+
+impl<'a> VmRef<'a> {
+    pub(crate) fn resolve(self) -> Result<VmId> {
+        match self {
+            VmRef::Id(id) => Ok(id),
+            VmRef::Name(name) => Hyperv::resolve_vm_name(name),
+        }
+    }
+}
+
+impl<'a> From<VmId> for VmRef<'a> {
+    fn from(id: VmId) -> Self {
+        VmRef::Id(id)
+    }
+}
+
+impl<'a> From<&'a VmId> for VmRef<'a> {
+    fn from(id: &'a VmId) -> Self {
+        VmRef::Id(*id)
+    }
+}
+
+impl<'a> From<&'a str> for VmRef<'a> {
+    fn from(name: &'a str) -> Self {
+        VmRef::Name(name)
+    }
+}
+
+impl<'a> From<&'a String> for VmRef<'a> {
+    fn from(name: &'a String) -> Self {
+        VmRef::Name(name)
+    }
+}
+
+#[derive(Deserialize)]
+struct NamedVm {
+    #[serde(rename = "Id")]
+    id: VmId,
+    #[serde(rename = "Name")]
+    name: String,
+}
+
+impl Hyperv {
+    // Hyper-V allows several VMs to share a name. Returns each such name with the Ids of the VMs using it.
+    // Names are compared case insensitively like Hyper-V itself does
+    pub fn find_duplicate_vm_names() -> Result<HashMap<String, Vec<VmId>>> {
+        let mut by_name: HashMap<String, Vec<VmId>> = HashMap::new();
+        for vm in Self::get_vm_names()? {
+            by_name.entry(vm.name.to_lowercase()).or_default().push(vm.id);
+        }
+
+        by_name.retain(|_, ids| ids.len() > 1);
+        Ok(by_name)
+    }
+
+    // Returns name itself if no VM has it, otherwise the first of "name-2", "name-3", ... that is free
+    pub fn unique_vm_name(name: &str) -> Result<String> {
+        let taken: Vec<String> = Self::get_vm_names()?.into_iter().map(|vm| vm.name.to_lowercase()).collect();
+        if !taken.contains(&name.to_lowercase()) {
+            return Ok(name.to_owned())
+        }
+
+        let mut suffix = 2;
+        loop {
+            let candidate = format!("{}-{}", name, suffix);
+            if !taken.contains(&candidate.to_lowercase()) {
+                return Ok(candidate)
+            }
+            suffix += 1;
+        }
+    }
+
+    // Name based APIs go through here so they fail with ErrorKind::AmbiguousName rather than
+    // silently acting on several VMs at once
+    pub fn resolve_vm_name(name: &str) -> Result<VmId> {
+        let ids: Vec<VmId> = Self::get_vm_names()?.into_iter()
+            .filter(|vm| vm.name.to_lowercase() == name.to_lowercase())
+            .map(|vm| vm.id)
+            .collect();
+
+        match ids.len() {
+            0 => Err(vm_name_not_found(name)),
+            1 => Ok(ids[0]),
+            _ => Err(ambiguous_name(name, ids)),
+        }
+    }
+
+    // Hyper-V does not stop the new name from clashing with another VM's. Use unique_vm_name() to avoid that
+    pub fn rename_vm(id: &VmId, new_name: &str) -> Result<Vm> {
+        if new_name.trim().is_empty() {
+            return Err(HypervError::new("VM name cannot be empty"))
+        }
+
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | rename-vm -NewName {} -Passthru -ErrorAction Stop | select-object -property {} | convertto-json",
+            id,
+            ps_quote(new_name),
+            VmProp::projection_of(&VmProp::ALL));
+        let vm: Option<Vm> = Self::spawn_mutation_and_deserialize(&command)?;
+        vm.ok_or_else(|| HypervError::new("Rename-VM did not return the renamed VM"))
+    }
+
+    // Looks the VM up on the host rather than listing them all. Fails with ErrorKind::NotFound if no VM
+    // has the name and with ErrorKind::AmbiguousName if several do
+    pub fn get_vm_by_name(name: &str) -> Result<Vm> {
+        let command = format!(
+            "convertto-json -InputObject @(get-vm -Name {} -ErrorAction SilentlyContinue | select-object -property {})",
+            ps_quote(&wildcard_escape(name)),
+            VmProp::projection_of(&VmProp::ALL));
+        let mut vms: Vec<Vm> = Self::spawn_and_deserialize_list(&command)?;
+        match vms.len() {
+            0 => Err(vm_name_not_found(name)),
+            1 => Ok(vms.remove(0)),
+            _ => Err(ambiguous_name(name, vms.iter().map(|vm| vm.id).collect())),
+        }
+    }
+
+    fn get_vm_names() -> Result<Vec<NamedVm>> {
+        let vms: Vec<NamedVm> = Self::spawn_and_deserialize_list("convertto-json -InputObject @(get-vm | select-object -property Id,Name)")?;
+        Ok(vms)
+    }
+}
+
+fn vm_name_not_found(name: &str) -> HypervError {
+    HypervError::with_kind(ErrorKind::NotFound, format!("No VM named '{}' exists", name))
+}
+
+fn ambiguous_name(name: &str, ids: Vec<VmId>) -> HypervError {
+    let list = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ");
+    HypervError::with_kind(ErrorKind::AmbiguousName(ids), format!("More than one VM is named '{}': {}", name, list))
+}
+
+// Get-VM -Name takes a wildcard pattern. Escapes the characters that would make a literal name one
+fn wildcard_escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if let '*' | '?' | '[' | ']' | '`' = c {
+            escaped.push('`');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_wildcards_in_names() {
+        assert_eq!(wildcard_escape("web [prod]*"), "web `[prod`]`*");
+        assert_eq!(wildcard_escape("plain"), "plain");
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{Hyperv, HypervError, Result, VSwitch, ps_quote};
+use serde_derive::Deserialize;
+use std::net::{IpAddr, Ipv4Addr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "TCP",
+            Protocol::Udp => "UDP",
+        }
+    }
+}
+
+// A NetNat static mapping forwarding a port on the host to a port on a VM behind the NAT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortForward {
+    pub id: u32,
+    pub nat_name: String,
+    pub protocol: Protocol,
+    pub external_port: u16,
+    pub internal_ip: IpAddr,
+    pub internal_port: u16,
+}
+
+#[derive(Deserialize)]
+struct RawPortForward {
+    #[serde(rename = "StaticMappingID")]
+    id: u32,
+    #[serde(rename = "NatName")]
+    nat_name: String,
+    #[serde(rename = "Protocol")]
+    protocol: String,
+    #[serde(rename = "ExternalPort")]
+    external_port: u16,
+    #[serde(rename = "InternalIPAddress")]
+    internal_ip: String,
+    #[serde(rename = "InternalPort")]
+    internal_port: u16,
+}
+
+impl RawPortForward {
+    fn into_port_forward(self) -> Result<PortForward> {
+        let protocol = match self.protocol.as_str() {
+            "TCP" => Protocol::Tcp,
+            "UDP" => Protocol::Udp,
+            p => return Err(HypervError::new(format!("Unexpected NAT mapping protocol '{}'", p))),
+        };
+        let internal_ip = self.internal_ip.parse::<IpAddr>()
+            .map_err(|e| HypervError::new(format!("Failed to parse IP address '{}': {}", self.internal_ip, e)))?;
+
+        Ok(PortForward {
+            id: self.id,
+            nat_name: self.nat_name,
+            protocol,
+            external_port: self.external_port,
+            internal_ip,
+            internal_port: self.internal_port,
+        })
+    }
+}
+
+// An internal switch whose VMs reach the outside world through NAT on the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NatNetwork {
+    pub switch: VSwitch,
+    // The NetNat object has the same name as the switch
+    pub nat_name: String,
+    pub subnet: Ipv4Addr,
+    pub prefix_length: u8,
+    // The host's address on the switch. Guests use it as their default gateway
+    pub gateway: Ipv4Addr,
+}
+
+const PORT_FORWARD_PROJECTION: &str = "StaticMappingID,NatName,@{Name='Protocol';Expression={$_.Protocol.ToString()}},ExternalPort,InternalIPAddress,InternalPort";
+
+impl Hyperv {
+    // Creates an internal switch, gives the host the first address of the subnet on it and puts a NetNat
+    // in front of the subnet, e.g. create_nat_network("lab", Ipv4Addr::new(192, 168, 100, 0), 24).
+    // Guests need static addresses in the subnet, or a DHCP server, since NetNat hands out none.
+    // If any step fails the switch is removed again
+    pub fn create_nat_network(name: &str, subnet: Ipv4Addr, prefix_length: u8) -> Result<NatNetwork> {
+        Self::require_elevation()?;
+        let gateway = nat_gateway(subnet, prefix_length)?;
+        let switch = VSwitch::create_internal(name)?;
+
+        let command = format!(
+            "$adapter = get-netadapter -Name ('vEthernet (' + {} + ')') -ErrorAction Stop;
+            new-netipaddress -InterfaceIndex $adapter.ifIndex -IPAddress '{}' -PrefixLength {} -ErrorAction Stop | out-null;
+            new-netnat -Name {} -InternalIPInterfaceAddressPrefix '{}/{}' -ErrorAction Stop | out-null",
+            ps_quote(name),
+            gateway,
+            prefix_length,
+            ps_quote(name),
+            subnet,
+            prefix_length);
+        if let Err(e) = Self::spawn_mutation(&command) {
+            let switch_id = switch.id;
+            return Err(match switch.remove() {
+                Ok(()) => e,
+                Err(re) => HypervError::new(format!("{}. Removing switch {} afterwards also failed: {}", e, switch_id, re)),
+            })
+        }
+
+        Ok(NatNetwork { switch, nat_name: name.to_owned(), subnet, prefix_length, gateway })
+    }
+
+    // Makes internal_port on the VM at vm_ip reachable through external_port on every host address
+    pub fn add_port_forward(nat: &str, external_port: u16, vm_ip: IpAddr, internal_port: u16, protocol: Protocol) -> Result<PortForward> {
+        Self::require_elevation()?;
+        let command = format!(
+            "add-netnatstaticmapping -NatName {} -Protocol {} -ExternalIPAddress '0.0.0.0/24' -ExternalPort {} -InternalIPAddress '{}' -InternalPort {} -ErrorAction Stop |
+                select-object -property {} | convertto-json",
+            ps_quote(nat),
+            protocol.as_str(),
+            external_port,
+            vm_ip,
+            internal_port,
+            PORT_FORWARD_PROJECTION);
+        let raw: Option<RawPortForward> = Self::spawn_mutation_and_deserialize(&command)?;
+        raw.ok_or_else(|| HypervError::new("Add-NetNatStaticMapping did not return the created mapping"))?
+            .into_port_forward()
+    }
+
+    pub fn list_port_forwards(nat: &str) -> Result<Vec<PortForward>> {
+        let command = format!(
+            "convertto-json -InputObject @(get-netnatstaticmapping -NatName {} -ErrorAction SilentlyContinue | select-object -property {})",
+            ps_quote(nat),
+            PORT_FORWARD_PROJECTION);
+        let raw: Vec<RawPortForward> = Self::spawn_and_deserialize_list(&command)?;
+        raw.into_iter().map(RawPortForward::into_port_forward).collect()
+    }
+
+    pub fn remove_port_forward(forward: &PortForward) -> Result<()> {
+        Self::require_elevation()?;
+        let command = format!(
+            "remove-netnatstaticmapping -NatName {} -StaticMappingID {} -Confirm:$false -ErrorAction Stop",
+            ps_quote(&forward.nat_name),
+            forward.id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+// The first host address of the subnet
+fn nat_gateway(subnet: Ipv4Addr, prefix_length: u8) -> Result<Ipv4Addr> {
+    if !(8..=30).contains(&prefix_length) {
+        return Err(HypervError::new(format!("Invalid prefix length {}. Must be between 8 and 30", prefix_length)))
+    }
+    let host_mask = u32::MAX >> prefix_length;
+    let subnet = u32::from(subnet);
+    if subnet & host_mask != 0 {
+        return Err(HypervError::new(format!("{}/{} is not a subnet address. Its host bits must be zero", Ipv4Addr::from(subnet), prefix_length)))
+    }
+    Ok(Ipv4Addr::from(subnet + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gateway_is_first_host_address() {
+        assert_eq!(nat_gateway(Ipv4Addr::new(192, 168, 100, 0), 24).unwrap(), Ipv4Addr::new(192, 168, 100, 1));
+        assert_eq!(nat_gateway(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap(), Ipv4Addr::new(10, 0, 0, 1));
+    }
+
+    #[test]
+    fn rejects_bad_subnets() {
+        assert!(nat_gateway(Ipv4Addr::new(192, 168, 100, 5), 24).is_err());
+        assert!(nat_gateway(Ipv4Addr::new(192, 168, 100, 0), 31).is_err());
+    }
+}
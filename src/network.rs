@@ -0,0 +1,157 @@
+use crate::{ErrorKind, Hyperv, HypervError, Result, VmId, VmProp, VmRef, VmState, ps_quote};
+use serde_derive::Deserialize;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NetworkAdapter {
+    #[serde(rename = "Name")]
+    pub name: String,
+    // As Hyper-V reports it, e.g. 00155D012345
+    #[serde(rename = "MacAddress")]
+    pub mac_address: String,
+    #[serde(rename = "DynamicMacAddressEnabled")]
+    pub dynamic_mac_address_enabled: bool,
+    #[serde(rename = "SwitchName")]
+    pub switch_name: Option<String>,
+    // Operational status, e.g. "Ok" or "Degraded"
+    #[serde(rename = "Status")]
+    pub status: String,
+}
+
+// The addresses the guest reports for one of its adapters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdapterAddresses {
+    pub adapter_name: String,
+    pub mac_address: String,
+    pub ip_addresses: Vec<IpAddr>,
+}
+
+#[derive(Deserialize)]
+struct RawAdapterAddresses {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "MacAddress")]
+    mac_address: String,
+    #[serde(rename = "IPAddresses")]
+    ip_addresses: Vec<String>,
+}
+
+pub(crate) const NETWORK_ADAPTER_PROJECTION: &str = "Name,MacAddress,DynamicMacAddressEnabled,SwitchName,@{Name='Status';Expression={($_.Status | foreach-object { $_.ToString() }) -join ', '}}";
+
+impl Hyperv {
+    pub fn list_network_adapters(id: &VmId) -> Result<Vec<NetworkAdapter>> {
+        let command = format!(
+            "convertto-json -InputObject @(get-vm -Id '{}' -ErrorAction Stop | get-vmnetworkadapter | select-object -property {})",
+            id,
+            NETWORK_ADAPTER_PROJECTION);
+        Self::spawn_and_deserialize_list(&command)
+    }
+
+    // Generation 2 VMs can have adapters added while running. Generation 1 VMs must be off
+    pub fn add_network_adapter(id: &VmId, name: &str, switch: Option<&str>) -> Result<NetworkAdapter> {
+        Self::check_adapter_hot_plug(id)?;
+        let switch_param = match switch {
+            Some(switch) => format!("-SwitchName {}", ps_quote(switch)),
+            None => "".to_owned(),
+        };
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | add-vmnetworkadapter -Name {} {} -Passthru -ErrorAction Stop | select-object -property {} | convertto-json",
+            id,
+            ps_quote(name),
+            switch_param,
+            NETWORK_ADAPTER_PROJECTION);
+        let adapter: Option<NetworkAdapter> = Self::spawn_mutation_and_deserialize(&command)?;
+        adapter.ok_or_else(|| HypervError::new("Add-VMNetworkAdapter did not return the added adapter"))
+    }
+
+    pub fn remove_network_adapter(id: &VmId, name: &str) -> Result<()> {
+        Self::check_adapter_hot_plug(id)?;
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | get-vmnetworkadapter -Name {} -ErrorAction Stop | remove-vmnetworkadapter -ErrorAction Stop",
+            id,
+            ps_quote(name));
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    fn check_adapter_hot_plug(id: &VmId) -> Result<()> {
+        let vm = Self::get_vm_with(id, &[VmProp::State, VmProp::Generation])?;
+        if vm.state != Some(VmState::Off) && vm.generation == Some(1) {
+            return Err(HypervError::with_kind(ErrorKind::NotSupported, "Network adapters of a Generation 1 VM can only be added or removed while it is off"))
+        }
+        Ok(())
+    }
+
+    // The addresses the guest reports through the Key-Value Pair Exchange integration service. Empty
+    // until the guest has booted far enough to run it, so callers waiting for an address should poll
+    pub fn get_vm_ip_addresses<'a, V: Into<VmRef<'a>>>(vm: V) -> Result<Vec<AdapterAddresses>> {
+        let id = vm.into().resolve()?;
+        let command = format!(
+            "convertto-json -InputObject @(get-vm -Id '{}' -ErrorAction Stop | get-vmnetworkadapter | select-object -property Name,MacAddress,@{{Name='IPAddresses';Expression={{@($_.IPAddresses)}}}})",
+            id);
+        let adapters: Vec<RawAdapterAddresses> = Self::spawn_and_deserialize_list(&command)?;
+        adapters.into_iter()
+            .map(|a| Ok(AdapterAddresses { adapter_name: a.name, mac_address: a.mac_address, ip_addresses: parse_ips(&a.ip_addresses)? }))
+            .collect()
+    }
+
+    // Finds the addresses a guest is using from the host side, for VMs on NAT or internal switches
+    // where the guest reports nothing through KVP. Looks in the host's neighbor (ARP/NDP) cache and,
+    // if the DHCP Server role is installed, in its leases. A guest on the Default Switch or another
+    // ICS or WinNAT network may not have talked to the host yet, so when the neighbor cache has no
+    // entry the addresses ICS has leased (hosts.ics) and those WinNAT has sessions for are probed,
+    // which makes every guest behind them answer and show up in the neighbor cache
+    pub fn discover_vm_ip_by_mac(mac: &str) -> Result<Vec<IpAddr>> {
+        let mac = normalize_mac(mac)?;
+        let command = format!(
+            "$mac = '{}';
+            function find-neighbor {{
+                @(get-netneighbor -ErrorAction SilentlyContinue |
+                    where-object {{ ($_.LinkLayerAddress -replace '[-:]', '') -eq $mac -and $_.State -ne 'Unreachable' }} |
+                    foreach-object {{ $_.IPAddress }})
+            }}
+            $ips = @(find-neighbor);
+            if (-not $ips) {{
+                $candidates = @();
+                $ics = join-path $env:SystemRoot 'System32\\drivers\\etc\\hosts.ics';
+                if (test-path -LiteralPath $ics) {{
+                    $candidates += @(get-content -LiteralPath $ics -ErrorAction SilentlyContinue |
+                        foreach-object {{ if ($_ -match '^\\s*(\\d+\\.\\d+\\.\\d+\\.\\d+)\\s') {{ $matches[1] }} }})
+                }}
+                if (get-command get-netnatsession -ErrorAction SilentlyContinue) {{
+                    $candidates += @(get-netnatsession -ErrorAction SilentlyContinue | foreach-object {{ $_.InternalSourceAddress }})
+                }}
+                $ping = new-object System.Net.NetworkInformation.Ping;
+                foreach ($candidate in @($candidates | select-object -Unique)) {{
+                    try {{ $ping.Send($candidate, 200) | out-null }} catch {{}}
+                }}
+                $ips = @(find-neighbor)
+            }}
+            if (get-command get-dhcpserverv4scope -ErrorAction SilentlyContinue) {{
+                $ips += @(get-dhcpserverv4scope -ErrorAction SilentlyContinue | get-dhcpserverv4lease -ErrorAction SilentlyContinue |
+                    where-object {{ ($_.ClientId -replace '[-:]', '') -eq $mac }} |
+                    foreach-object {{ $_.IPAddress.ToString() }})
+            }}
+            convertto-json -InputObject @($ips | select-object -Unique)",
+            mac);
+
+        let ips: Vec<String> = Self::spawn_and_deserialize_list(&command)?;
+        parse_ips(&ips)
+    }
+}
+
+fn parse_ips(ips: &[String]) -> Result<Vec<IpAddr>> {
+    ips.iter()
+        .map(|ip| ip.parse::<IpAddr>().map_err(|e| HypervError::new(format!("Failed to parse IP address '{}': {}", ip, e))))
+        .collect()
+}
+
+// Accepts MACs written as 00155D012345, 00-15-5D-01-23-45 or 00:15:5d:01:23:45 and returns the first form
+pub(crate) fn normalize_mac(mac: &str) -> Result<String> {
+    let normalized: String = mac.chars().filter(|c| *c != '-' && *c != ':').collect::<String>().to_uppercase();
+    if normalized.len() != 12 || !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+        Err(HypervError::new(format!("Invalid MAC address '{}'", mac)))
+    } else {
+        Ok(normalized)
+    }
+}
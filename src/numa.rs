@@ -0,0 +1,67 @@
+use crate::{ErrorKind, Hyperv, HypervError, Result, VmId, VmProp, VmState};
+use serde_derive::Deserialize;
+
+// How a VM's processors and memory are split into virtual NUMA nodes. Hyper-V derives the defaults
+// from the host's topology. Guests like SQL Server size their thread pools and allocators from these
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct VmNumaSettings {
+    #[serde(rename = "MaximumCountPerNumaNode")]
+    pub max_processors_per_node: u32,
+    #[serde(rename = "MaximumCountPerNumaSocket")]
+    pub max_nodes_per_socket: u32,
+    #[serde(rename = "MaximumAmountPerNumaNodeBytes")]
+    pub max_memory_per_node_bytes: u64,
+}
+
+impl Hyperv {
+    pub fn get_vm_numa_settings(id: &VmId) -> Result<VmNumaSettings> {
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop;
+            $processor = $vm | get-vmprocessor -ErrorAction Stop;
+            [pscustomobject]@{{
+                MaximumCountPerNumaNode = $processor.MaximumCountPerNumaNode;
+                MaximumCountPerNumaSocket = $processor.MaximumCountPerNumaSocket;
+                MaximumAmountPerNumaNodeBytes = ($vm | get-vmmemory -ErrorAction Stop).MaximumAmountPerNumaNodeBytes
+            }} | convertto-json",
+            id);
+        let settings: Option<VmNumaSettings> = Self::spawn_and_deserialize(&command)?;
+        settings.ok_or_else(|| HypervError::new("VM NUMA query returned no output"))
+    }
+
+    // The VM must be off
+    pub fn set_vm_numa_settings(id: &VmId, settings: &VmNumaSettings) -> Result<()> {
+        if settings.max_processors_per_node == 0 || settings.max_nodes_per_socket == 0 || settings.max_memory_per_node_bytes == 0 {
+            return Err(HypervError::new("NUMA limits must be greater than zero"))
+        }
+        let state = Self::get_vm_with(id, &[VmProp::State])?.state;
+        if state != Some(VmState::Off) {
+            let state = state.unwrap_or_else(|| VmState::Other("Unknown".to_owned()));
+            return Err(HypervError::with_kind(ErrorKind::InvalidState(state), "NUMA settings can only be changed while the VM is off"))
+        }
+
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop;
+            $vm | set-vmprocessor -MaximumCountPerNumaNode {} -MaximumCountPerNumaSocket {} -ErrorAction Stop;
+            $vm | set-vmmemory -MaximumAmountPerNumaNodeBytes {} -ErrorAction Stop",
+            id,
+            settings.max_processors_per_node,
+            settings.max_nodes_per_socket,
+            settings.max_memory_per_node_bytes);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // NUMA spanning is a host wide setting rather than a per VM one. When enabled, a VM may get
+    // processors and memory from more than one physical node, trading performance for fitting more VMs
+    pub fn is_numa_spanning_enabled() -> Result<bool> {
+        let enabled: Option<bool> = Self::spawn_and_deserialize("(get-vmhost -ErrorAction Stop).NumaSpanningEnabled | convertto-json")?;
+        enabled.ok_or_else(|| HypervError::new("Get-VMHost returned no output"))
+    }
+
+    // Takes effect once the Virtual Machine Management Service restarts. Running VMs are unaffected until then
+    pub fn set_numa_spanning(enabled: bool) -> Result<()> {
+        Self::require_elevation()?;
+        Self::spawn_mutation(&format!("set-vmhost -NumaSpanningEnabled ${} -ErrorAction Stop", enabled))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{ErrorKind, Hyperv, HypervError, Result};
+use crate::config::{self, encode_command};
+use crate::{progress, queue};
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Limit for every PowerShell command run outside an Operation with its own timeout, if any
+static DEFAULT_TIMEOUT: RwLock<Option<Duration>> = RwLock::new(None);
+
+thread_local! {
+    static CURRENT: RefCell<Option<Operation>> = const { RefCell::new(None) };
+}
+
+type ProgressCallback = Arc<dyn Fn(u8, &str) + Send + Sync>;
+
+// Lets a caller give up on operations, e.g. a hung Import-VM or Export-VM. Operations performed inside
+// run() fail with ErrorKind::Timeout once a PowerShell command they run exceeds the timeout, and with
+// ErrorKind::Cancelled as soon as cancel() is called from any thread. Either way the PowerShell process
+// is killed. Work Hyper-V has already started on the host's side, such as copying files, may still
+// finish or be rolled back by Hyper-V in its own time
+#[derive(Clone)]
+pub struct Operation {
+    timeout: Option<Duration>,
+    cancelled: Arc<AtomicBool>,
+    progress: Option<ProgressCallback>,
+}
+
+impl Operation {
+    // Without timeout() commands are limited by Hyperv::set_default_timeout(), if set
+    pub fn new() -> Self {
+        Self { timeout: None, cancelled: Arc::new(AtomicBool::new(false)), progress: None }
+    }
+
+    // Applies to each PowerShell command separately, not to the operations as a whole
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // Called with the percentage done and the activity, e.g. "Exporting virtual machine", as import_vm(),
+    // export_vm(), Vhd::convert() and Vhd::optimize() progress. Called on the thread running the operation
+    pub fn on_progress<F: Fn(u8, &str) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.progress = Some(Arc::new(f));
+        self
+    }
+
+    pub(crate) fn reports_progress(&self) -> bool {
+        self.progress.is_some()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // Runs f with every operation it performs on the calling thread subject to this timeout and
+    // cancellation. Once cancelled, every command f goes on to run fails without being started
+    pub fn run<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        with_operation(Some(self), f)
+    }
+}
+
+impl Default for Operation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hyperv {
+    // Limits every PowerShell command not run under an Operation with a timeout of its own. Pass None,
+    // the default, to let commands run for as long as they take
+    pub fn set_default_timeout(timeout: Option<Duration>) {
+        *DEFAULT_TIMEOUT.write().unwrap_or_else(|e| e.into_inner()) = timeout;
+    }
+
+    pub fn default_timeout() -> Option<Duration> {
+        *DEFAULT_TIMEOUT.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// The operation operations on the calling thread run under, if any
+pub(crate) fn current_operation() -> Option<Operation> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+// For operations that spread their work over threads, which must stay under the caller's operation
+pub(crate) fn with_operation<T, F: FnOnce() -> T>(operation: Option<&Operation>, f: F) -> T {
+    let previous = CURRENT.with(|c| c.replace(operation.cloned()));
+    let result = f();
+    CURRENT.with(|c| *c.borrow_mut() = previous);
+    result
+}
+
+// Whether commands on the calling thread must run in a process that can be killed or whose output
+// must be watched as it comes
+pub(crate) fn is_limited() -> bool {
+    current_operation().is_some() || Hyperv::default_timeout().is_some()
+}
+
+// Runs the command in its own process, watching the timeout and cancellation of the calling thread's
+// operation and killing the process on either. Returns stdout
+pub(crate) fn run_killable(command: &str) -> Result<String> {
+    let operation = current_operation();
+    let cancelled = || operation.as_ref().is_some_and(Operation::is_cancelled);
+    let timeout = operation.as_ref().and_then(|o| o.timeout).or_else(Hyperv::default_timeout);
+    if cancelled() {
+        return Err(HypervError::with_kind(ErrorKind::Cancelled, "The operation was cancelled"))
+    }
+
+    let _slot = queue::acquire_slot();
+    let mut child = {
+        let (command, _credential_scopes) = config::prepare_command(command);
+        Command::new("powershell.exe")
+            .args(["-NoProfile", "-NonInteractive", "-EncodedCommand", &encode_command(&command)])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| HypervError::new(format!("Failed to spawn PowerShell process: {}", e)))?
+    };
+    // Read on their own threads so a command writing more than a pipe holds can't block on us
+    let lines = read_lines(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+
+    let started = Instant::now();
+    let mut stdout = String::new();
+    let status = loop {
+        match lines.recv_timeout(POLL_INTERVAL) {
+            Ok(line) => {
+                match (progress::parse_line(&line), operation.as_ref().and_then(|o| o.progress.as_ref())) {
+                    (Some((percent, activity)), Some(report)) => report(percent, activity),
+                    (Some(_), None) => {},
+                    (None, _) => {
+                        stdout.push_str(&line);
+                        stdout.push('\n');
+                    },
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => {},
+            // All output has been read. Only the exit remains
+            Err(RecvTimeoutError::Disconnected) => {
+                let status = child.try_wait()
+                    .map_err(|e| HypervError::new(format!("Failed while waiting for PowerShell process: {}", e)))?;
+                if let Some(status) = status {
+                    break status
+                }
+                thread::sleep(POLL_INTERVAL);
+            },
+        }
+        if cancelled() {
+            kill(&mut child);
+            return Err(HypervError::with_kind(ErrorKind::Cancelled, "The operation was cancelled"))
+        }
+        if let Some(timeout) = timeout {
+            if started.elapsed() >= timeout {
+                kill(&mut child);
+                return Err(HypervError::with_kind(ErrorKind::Timeout, format!("PowerShell command did not complete within {:?}", timeout)))
+            }
+        }
+    };
+
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        return Err(crate::process_failure(status.code(), stdout.as_bytes(), &stderr))
+    }
+    Ok(stdout)
+}
+
+fn read_lines(pipe: Option<ChildStdout>) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        if let Some(pipe) = pipe {
+            for line in BufReader::new(pipe).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if sender.send(line).is_err() {
+                    break
+                }
+            }
+        }
+    });
+    receiver
+}
+
+fn read_to_end<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
@@ -0,0 +1,80 @@
+use crate::{ErrorKind, Hyperv, HypervError, Result};
+use crate::remote;
+use serde_derive::Deserialize;
+use std::sync::Mutex;
+
+// SID of the built-in Hyper-V Administrators group. Names are localized so it is looked up by SID
+const HYPERV_ADMINISTRATORS_SID: &str = "S-1-5-32-578";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+struct Privileges {
+    #[serde(rename = "Elevated")]
+    elevated: bool,
+    #[serde(rename = "HypervAdministrator")]
+    hyperv_administrator: bool,
+}
+
+// Privileges of the account the backend runs as on each host, None being this one. Looked up once per
+// host and forgotten when that account changes
+static PRIVILEGES: Mutex<Vec<(Option<String>, Privileges)>> = Mutex::new(Vec::new());
+
+pub(crate) fn forget_privileges() {
+    PRIVILEGES.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+impl Hyperv {
+    // Whether the backend runs with a full administrator token
+    pub fn is_elevated() -> Result<bool> {
+        Ok(Self::privileges()?.elevated)
+    }
+
+    // Whether the backend may manage VMs, either by being elevated or by being in Hyper-V Administrators.
+    // Host level operations such as mounting disks or changing boot settings need elevation regardless
+    pub fn has_hyperv_admin_rights() -> Result<bool> {
+        let privileges = Self::privileges()?;
+        Ok(privileges.elevated || privileges.hyperv_administrator)
+    }
+
+    // Every mutating operation checks this up front so that it fails with ErrorKind::InsufficientPrivileges
+    // rather than with an access denied error half way through
+    pub(crate) fn require_hyperv_admin_rights() -> Result<()> {
+        // A JEA endpoint runs commands under its own account, whatever ours is
+        if Self::constrained_endpoint().is_some() || Self::has_hyperv_admin_rights()? {
+            return Ok(())
+        }
+
+        Err(HypervError::with_kind(
+            ErrorKind::InsufficientPrivileges { hyperv_administrators_suffices: true },
+            "This operation requires an elevated process or membership in the Hyper-V Administrators group"))
+    }
+
+    pub(crate) fn require_elevation() -> Result<()> {
+        if Self::constrained_endpoint().is_some() || Self::is_elevated()? {
+            return Ok(())
+        }
+
+        Err(HypervError::with_kind(
+            ErrorKind::InsufficientPrivileges { hyperv_administrators_suffices: false },
+            "This operation changes host configuration and requires an elevated process. Membership in Hyper-V Administrators is not enough"))
+    }
+
+    fn privileges() -> Result<Privileges> {
+        let host = remote::current_host().map(|h| h.computer_name().to_lowercase());
+        let known = PRIVILEGES.lock().unwrap_or_else(|e| e.into_inner()).iter().find(|(h, _)| *h == host).map(|(_, p)| *p);
+        if let Some(privileges) = known {
+            return Ok(privileges)
+        }
+
+        let command = format!(
+            "$principal = new-object Security.Principal.WindowsPrincipal([Security.Principal.WindowsIdentity]::GetCurrent());
+            [pscustomobject]@{{
+                Elevated = $principal.IsInRole([Security.Principal.WindowsBuiltInRole]::Administrator);
+                HypervAdministrator = $principal.IsInRole((new-object Security.Principal.SecurityIdentifier('{}')))
+            }} | convertto-json",
+            HYPERV_ADMINISTRATORS_SID);
+        let privileges: Option<Privileges> = Self::spawn_and_deserialize(&command)?;
+        let privileges = privileges.ok_or_else(|| HypervError::new("Privilege query returned no output"))?;
+        PRIVILEGES.lock().unwrap_or_else(|e| e.into_inner()).push((host, privileges));
+        Ok(privileges)
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{ErrorKind, Hyperv, HypervError, Result, VmId, VmProp, VmState};
+use serde_derive::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProcessorSettings {
+    #[serde(rename = "Count")]
+    pub count: u32,
+    // Percentages of the VM's processor capacity
+    #[serde(rename = "Reserve")]
+    pub reserve: u32,
+    #[serde(rename = "Maximum")]
+    pub maximum: u32,
+    #[serde(rename = "RelativeWeight")]
+    pub relative_weight: u32,
+    #[serde(rename = "ExposeVirtualizationExtensions", default)]
+    pub expose_virtualization_extensions: bool,
+}
+
+pub(crate) const PROCESSOR_PROJECTION: &str = "Count,Reserve,Maximum,RelativeWeight,ExposeVirtualizationExtensions";
+
+#[derive(Deserialize)]
+struct ProcessorCountCheck {
+    #[serde(rename = "State")]
+    state: VmState,
+    #[serde(rename = "HostProcessors")]
+    host_processors: u32,
+}
+
+impl Hyperv {
+    pub fn get_processor(id: &VmId) -> Result<ProcessorSettings> {
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | get-vmprocessor -ErrorAction Stop | select-object -property {} | convertto-json", id, PROCESSOR_PROJECTION);
+        let settings: Option<ProcessorSettings> = Self::spawn_and_deserialize(&command)?;
+        settings.ok_or_else(|| HypervError::new("Get-VMProcessor returned no output"))
+    }
+
+    // Hyper-V only changes the number of virtual processors of a VM that is off, not one that is running or saved.
+    // A VM cannot have more virtual processors than the host has logical ones
+    pub fn set_processor_count(id: &VmId, count: u32) -> Result<()> {
+        if count == 0 {
+            return Err(HypervError::new("Processor count must be at least 1"))
+        }
+
+        let command = format!(
+            "[pscustomobject]@{{
+                State = (get-vm -Id '{}' -ErrorAction Stop).State.ToString();
+                HostProcessors = (get-vmhost -ErrorAction Stop).LogicalProcessorCount
+            }} | convertto-json",
+            id);
+        let check: Option<ProcessorCountCheck> = Self::spawn_and_deserialize(&command)?;
+        let check = check.ok_or_else(|| HypervError::new("VM processor query returned no output"))?;
+
+        if check.state != VmState::Off {
+            return Err(HypervError::with_kind(ErrorKind::InvalidState(check.state), "The processor count can only be changed while the VM is off"))
+        }
+        if count > check.host_processors {
+            return Err(HypervError::new(format!("Cannot give the VM {} processors. The host only has {} logical processors", count, check.host_processors)))
+        }
+
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | set-vmprocessor -Count {} -ErrorAction Stop", id, count);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Lets the guest run its own hypervisor, e.g. Hyper-V inside Hyper-V on CI hosts. The VM must be off.
+    // Dynamic memory is not supported together with this, and the guest needs MAC address spoofing on its
+    // adapters for its own VMs to reach the network
+    pub fn set_nested_virtualization(id: &VmId, enabled: bool) -> Result<()> {
+        let state = Self::get_vm_with(id, &[VmProp::State])?.state;
+        if state != Some(VmState::Off) {
+            let state = state.unwrap_or_else(|| VmState::Other("Unknown".to_owned()));
+            return Err(HypervError::with_kind(ErrorKind::InvalidState(state), "Nested virtualization can only be changed while the VM is off"))
+        }
+
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | set-vmprocessor -ExposeVirtualizationExtensions ${} -ErrorAction Stop",
+            id,
+            enabled);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
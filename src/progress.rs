@@ -0,0 +1,49 @@
+use crate::operation;
+
+// Prefixes the lines carrying progress among a command's output
+const MARKER: &str = "hyperv_rs-progress:";
+
+// Turns a cmdlet invocation taking -AsJob into an expression with the same output that, when the calling
+// thread's Operation wants progress, runs the cmdlet as a job and reports the job's progress records.
+// Write-Host is used since unlike [Console]::Out it also reaches us from a remote host
+pub(crate) fn tracked(invocation: &str) -> String {
+    let wants_progress = operation::current_operation().is_some_and(|o| o.reports_progress());
+    if !wants_progress {
+        return invocation.to_owned()
+    }
+    format!(
+        "(& {{
+            $job = {} -AsJob;
+            $last = $null;
+            while ($job.State -eq 'NotStarted' -or $job.State -eq 'Running') {{
+                $record = @($job.ChildJobs | foreach-object {{ $_.Progress }}) | select-object -Last 1;
+                if ($record -and $record.PercentComplete -ge 0) {{
+                    $line = '{}' + $record.PercentComplete + ':' + $record.Activity;
+                    if ($line -ne $last) {{ write-host $line; $last = $line }}
+                }}
+                start-sleep -Milliseconds 500
+            }}
+            receive-job -Job $job -Wait -AutoRemoveJob -ErrorAction Stop
+        }})",
+        invocation,
+        MARKER)
+}
+
+// The percentage and activity of a progress line
+pub(crate) fn parse_line(line: &str) -> Option<(u8, &str)> {
+    let mut parts = line.trim_end().strip_prefix(MARKER)?.splitn(2, ':');
+    let percent = parts.next()?.parse::<u8>().ok()?;
+    Some((percent.min(100), parts.next().unwrap_or("")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_progress_lines() {
+        assert_eq!(parse_line("hyperv_rs-progress:42:Exporting: step 2"), Some((42, "Exporting: step 2")));
+        assert_eq!(parse_line("hyperv_rs-progress:x:Exporting"), None);
+        assert_eq!(parse_line("{\"Id\": 1}"), None);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{Hyperv, HypervError, Result};
+use std::cell::Cell;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_PROCESSES: usize = 4;
+const DEFAULT_MAX_STARTS_PER_SEC: u32 = 10;
+
+// Interactive operations (lifecycle verbs, queries a user is waiting on) run at High, which is the
+// default. Monitoring, metrics and exports should run at Background so they never hold up the former
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    Background,
+}
+
+struct Slots {
+    max: usize,
+    in_use: usize,
+    waiting_high: usize,
+}
+
+// Caps how many PowerShell processes run at once. A background operation only gets a slot when no
+// high priority operation is waiting for one
+static SLOTS: Mutex<Slots> = Mutex::new(Slots { max: DEFAULT_MAX_PROCESSES, in_use: 0, waiting_high: 0 });
+static SLOT_FREED: Condvar = Condvar::new();
+
+struct StartRate {
+    interval: Option<Duration>,
+    next_start: Option<Instant>,
+}
+
+// Spaces out process starts. VMMS gets unstable when flooded with queries even if only a few run at once
+static START_RATE: Mutex<StartRate> = Mutex::new(StartRate {
+    interval: Some(Duration::from_nanos(1_000_000_000 / DEFAULT_MAX_STARTS_PER_SEC as u64)),
+    next_start: None,
+});
+
+thread_local! {
+    static PRIORITY: Cell<Priority> = const { Cell::new(Priority::High) };
+}
+
+pub(crate) fn current_priority() -> Priority {
+    PRIORITY.with(|p| p.get())
+}
+
+pub(crate) struct Slot;
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        SLOTS.lock().unwrap_or_else(|e| e.into_inner()).in_use -= 1;
+        SLOT_FREED.notify_all();
+    }
+}
+
+// Blocks until the calling thread's operation may start a PowerShell process
+pub(crate) fn acquire_slot() -> Slot {
+    let priority = PRIORITY.with(|p| p.get());
+    let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+
+    match priority {
+        Priority::High => {
+            slots.waiting_high += 1;
+            while slots.in_use >= slots.max {
+                slots = SLOT_FREED.wait(slots).unwrap_or_else(|e| e.into_inner());
+            }
+            slots.waiting_high -= 1;
+        },
+        Priority::Background => {
+            while slots.in_use >= slots.max || slots.waiting_high > 0 {
+                slots = SLOT_FREED.wait(slots).unwrap_or_else(|e| e.into_inner());
+            }
+        },
+    }
+
+    slots.in_use += 1;
+    drop(slots);
+
+    // Throttled only once the slot is ours so waiting on the start rate cannot reorder priorities
+    let slot = Slot;
+    throttle();
+    slot
+}
+
+fn throttle() {
+    let wait = {
+        let mut rate = START_RATE.lock().unwrap_or_else(|e| e.into_inner());
+        let interval = match rate.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let now = Instant::now();
+        let start = rate.next_start.map_or(now, |next| next.max(now));
+        rate.next_start = Some(start + interval);
+        start - now
+    };
+
+    if wait > Duration::from_secs(0) {
+        thread::sleep(wait);
+    }
+}
+
+impl Hyperv {
+    // Runs f with every operation it issues on this thread scheduled at the given priority
+    pub fn with_priority<T, F: FnOnce() -> T>(priority: Priority, f: F) -> T {
+        let previous = PRIORITY.with(|p| p.replace(priority));
+        let result = f();
+        PRIORITY.with(|p| p.set(previous));
+        result
+    }
+
+    // The most PowerShell processes this crate runs at the same time. Defaults to 4
+    pub fn set_max_concurrent_processes(max: usize) -> Result<()> {
+        if max == 0 {
+            return Err(HypervError::new("At least one concurrent process must be allowed"))
+        }
+
+        SLOTS.lock().unwrap_or_else(|e| e.into_inner()).max = max;
+        SLOT_FREED.notify_all();
+        Ok(())
+    }
+
+    // The most PowerShell processes this crate starts per second. Defaults to 10. Pass None to not limit it
+    pub fn set_max_process_starts_per_sec(max: Option<u32>) -> Result<()> {
+        let interval = match max {
+            Some(0) => return Err(HypervError::new("At least one process start per second must be allowed")),
+            Some(max) => Some(Duration::from_nanos(1_000_000_000 / u64::from(max))),
+            None => None,
+        };
+
+        let mut rate = START_RATE.lock().unwrap_or_else(|e| e.into_inner());
+        rate.interval = interval;
+        rate.next_start = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{Credential, Hyperv, HypervError, Result, Vm};
+use std::cell::RefCell;
+
+thread_local! {
+    static TARGET: RefCell<Option<HypervHost>> = const { RefCell::new(None) };
+}
+
+// Another Hyper-V host, managed over PowerShell remoting (WinRM). Every operation run through run() goes
+// to that host instead of this one, e.g. host.run(|| Hyperv::start_vm(&id)). Paths passed to operations
+// are then paths on that host. The host must have remoting enabled and this account must be allowed to
+// use it, which by default takes membership in its Administrators group
+#[derive(Debug, Clone)]
+pub struct HypervHost {
+    computer_name: String,
+    credential: Option<Credential>,
+}
+
+impl HypervHost {
+    // Checks that the host can be reached and runs Hyper-V. Connects as the current account
+    pub fn connect(computer_name: &str) -> Result<Self> {
+        Self::connect_as(computer_name, None)
+    }
+
+    // For hosts in another domain or a workgroup, where the current account means nothing. Workgroup
+    // hosts must also be in this machine's WinRM TrustedHosts list
+    pub fn connect_with_credential(computer_name: &str, credential: Credential) -> Result<Self> {
+        Self::connect_as(computer_name, Some(credential))
+    }
+
+    fn connect_as(computer_name: &str, credential: Option<Credential>) -> Result<Self> {
+        if computer_name.trim().is_empty() {
+            return Err(HypervError::new("Computer name cannot be empty"))
+        }
+        let host = Self { computer_name: computer_name.to_owned(), credential };
+        host.run(|| Hyperv::spawn_and_wait("get-vmhost -ErrorAction Stop | out-null"))
+            .map_err(|mut e| {
+                e.msg = format!("Failed to connect to Hyper-V host '{}': {}", computer_name, e.msg);
+                e
+            })?;
+        Ok(host)
+    }
+
+    pub fn computer_name(&self) -> &str {
+        &self.computer_name
+    }
+
+    pub(crate) fn credential(&self) -> Option<&Credential> {
+        self.credential.as_ref()
+    }
+
+    // Runs f with every operation it performs on the calling thread going to this host. Calls nest, the
+    // innermost host wins
+    pub fn run<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        with_host(Some(self), f)
+    }
+
+    pub fn get_vms(&self) -> Result<Vec<Vm>> {
+        self.run(Hyperv::get_vms)
+    }
+}
+
+// The host operations on the calling thread go to. None for this one
+pub(crate) fn current_host() -> Option<HypervHost> {
+    TARGET.with(|t| t.borrow().clone())
+}
+
+pub(crate) fn is_local() -> bool {
+    TARGET.with(|t| t.borrow().is_none())
+}
+
+// For operations that spread their work over threads, which must keep going to the caller's host
+pub(crate) fn with_host<T, F: FnOnce() -> T>(host: Option<&HypervHost>, f: F) -> T {
+    let previous = TARGET.with(|t| t.replace(host.cloned()));
+    let result = f();
+    TARGET.with(|t| *t.borrow_mut() = previous);
+    result
+}
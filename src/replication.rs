@@ -0,0 +1,383 @@
+use crate::{Hyperv, HypervError, Result, Vm, VmId, VmProp, convert, ps_quote};
+use serde_derive::Deserialize;
+use uuid::Uuid;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Hyper-V Replica only supports these replication frequencies
+const REPLICATION_FREQUENCIES_SECS: [u32; 3] = [30, 300, 900];
+const MAX_RECOVERY_POINTS: u32 = 24;
+const MAX_APP_CONSISTENT_INTERVAL_HOURS: u64 = 12;
+const KERBEROS_PORT: u16 = 80;
+const CERTIFICATE_PORT: u16 = 443;
+const HTTPS_LISTENER_FIREWALL_RULE: &str = "VIRT-HVRHTTPSL-In-TCP-NoScope";
+
+// Kerberos only works between domain joined hosts. Workgroup hosts must use certificates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationAuth {
+    Kerberos,
+    Certificate { thumbprint: String },
+}
+
+impl ReplicationAuth {
+    fn params(&self) -> Result<String> {
+        match self {
+            ReplicationAuth::Kerberos => Ok(format!("-AuthenticationType Kerberos -ReplicaServerPort {}", KERBEROS_PORT)),
+            ReplicationAuth::Certificate { thumbprint } => {
+                let thumbprint = validate_thumbprint(thumbprint)?;
+                Ok(format!("-AuthenticationType Certificate -ReplicaServerPort {} -CertificateThumbprint '{}'", CERTIFICATE_PORT, thumbprint))
+            },
+        }
+    }
+}
+
+// The role of the VM on the host queried
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationMode {
+    Primary,
+    Replica,
+    // The copy of a replica VM a test failover creates
+    TestReplica,
+    // A replica of a replica
+    ExtendedReplica,
+    Other(String),
+}
+
+impl ReplicationMode {
+    fn from(mode: &str) -> Self {
+        match mode {
+            "Primary" => ReplicationMode::Primary,
+            "Replica" => ReplicationMode::Replica,
+            "TestReplica" => ReplicationMode::TestReplica,
+            "ExtendedReplica" => ReplicationMode::ExtendedReplica,
+            mode => ReplicationMode::Other(mode.to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationState {
+    // Enabled but start_initial_replication() has not been called yet
+    ReadyForInitialReplication,
+    InitialReplicationInProgress,
+    // On the replica, until the primary's initial copy arrives
+    WaitingForInitialReplication,
+    Replicating,
+    PreparedForFailover,
+    // Failed over, until the failover is completed or cancelled
+    FailedOverWaitingCompletion,
+    FailedOver,
+    Suspended,
+    Resynchronizing,
+    Error,
+    // States of newer Hyper-V versions or rarely seen ones, as Hyper-V names them
+    Other(String),
+}
+
+impl ReplicationState {
+    fn from(state: &str) -> Self {
+        match state {
+            "ReadyForInitialReplication" => ReplicationState::ReadyForInitialReplication,
+            "InitialReplicationInProgress" => ReplicationState::InitialReplicationInProgress,
+            "WaitingForInitialReplication" => ReplicationState::WaitingForInitialReplication,
+            "Replicating" => ReplicationState::Replicating,
+            "PreparedForFailover" => ReplicationState::PreparedForFailover,
+            "FailedOverWaitingCompletion" => ReplicationState::FailedOverWaitingCompletion,
+            "FailedOver" => ReplicationState::FailedOver,
+            "Suspended" => ReplicationState::Suspended,
+            "Resynchronizing" => ReplicationState::Resynchronizing,
+            "Error" => ReplicationState::Error,
+            state => ReplicationState::Other(state.to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicationHealth {
+    Normal,
+    // E.g. replication cycles were missed but it is still going
+    Warning,
+    // Replication has stopped and needs attention
+    Critical,
+    NotApplicable,
+}
+
+#[derive(Debug, Clone)]
+pub struct Replication {
+    pub mode: ReplicationMode,
+    pub state: ReplicationState,
+    pub health: ReplicationHealth,
+    pub primary_server: String,
+    pub replica_server: String,
+    pub frequency_secs: u32,
+    pub last_replication_time: Option<SystemTime>,
+}
+
+#[derive(Deserialize)]
+struct RawReplication {
+    #[serde(rename = "Mode")]
+    mode: String,
+    #[serde(rename = "State")]
+    state: String,
+    #[serde(rename = "Health")]
+    health: String,
+    #[serde(rename = "PrimaryServer")]
+    primary_server: String,
+    #[serde(rename = "ReplicaServer")]
+    replica_server: String,
+    #[serde(rename = "FrequencySec")]
+    frequency_secs: u32,
+    #[serde(rename = "LastReplicationTime", default, deserialize_with = "convert::deserialize_datetime")]
+    last_replication_time: Option<SystemTime>,
+}
+
+impl RawReplication {
+    fn into_replication(self) -> Result<Replication> {
+        let health = match self.health.as_str() {
+            "Normal" => ReplicationHealth::Normal,
+            "Warning" => ReplicationHealth::Warning,
+            "Critical" => ReplicationHealth::Critical,
+            "NotApplicable" => ReplicationHealth::NotApplicable,
+            h => return Err(HypervError::new(format!("Unexpected replication health '{}'", h))),
+        };
+        Ok(Replication {
+            mode: ReplicationMode::from(&self.mode),
+            state: ReplicationState::from(&self.state),
+            health,
+            primary_server: self.primary_server,
+            replica_server: self.replica_server,
+            frequency_secs: self.frequency_secs,
+            last_replication_time: self.last_replication_time,
+        })
+    }
+}
+
+// A point-in-time copy of a VM kept on the replica server that failover can roll back to
+#[derive(Debug, Clone)]
+pub struct RecoveryPoint {
+    pub id: Uuid,
+    pub name: String,
+    pub created: SystemTime,
+}
+
+#[derive(Deserialize)]
+struct RawRecoveryPoint {
+    #[serde(rename = "Id")]
+    id: Uuid,
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "CreationTime")]
+    creation_time: u64,
+}
+
+impl From<RawRecoveryPoint> for RecoveryPoint {
+    fn from(raw: RawRecoveryPoint) -> Self {
+        Self {
+            id: raw.id,
+            name: raw.name,
+            created: UNIX_EPOCH + Duration::from_millis(raw.creation_time),
+        }
+    }
+}
+
+impl Hyperv {
+    // Turns this host into a replica server accepting certificate authenticated replication over HTTPS from any primary
+    pub fn enable_replica_listener_with_certificate<P: AsRef<Path>>(thumbprint: &str, storage_path: P) -> Result<()> {
+        Self::require_elevation()?;
+        let thumbprint = validate_thumbprint(thumbprint)?;
+        let storage_path = storage_path.as_ref().to_str().ok_or_else(|| HypervError::new("Bad path"))?;
+        let command = format!(
+            "set-vmreplicationserver -ReplicationEnabled $true -AllowedAuthenticationType Certificate -CertificateAuthenticationPort {} -CertificateThumbprint '{}' -ReplicationAllowedFromAnyServer $true -DefaultStorageLocation {} -ErrorAction Stop;
+            enable-netfirewallrule -Name '{}' -ErrorAction Stop",
+            CERTIFICATE_PORT,
+            thumbprint,
+            ps_quote(storage_path),
+            HTTPS_LISTENER_FIREWALL_RULE);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Replica servers that are cluster nodes are addressed through the cluster's Replica Broker
+    // rather than the node itself
+    pub fn enable_replication(id: &VmId, replica_server: &str, auth: &ReplicationAuth) -> Result<()> {
+        let replica_server = Self::resolve_replica_server(replica_server)?;
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | enable-vmreplication -ReplicaServerName {} {} -ErrorAction Stop",
+            id,
+            ps_quote(&replica_server),
+            auth.params()?);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Sends the initial copy of the VM over the network. Replication proper starts once it has arrived.
+    // Watch its progress with get_replication()
+    pub fn start_initial_replication(id: &VmId) -> Result<()> {
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | start-vminitialreplication -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // None if replication is not enabled for the VM
+    pub fn get_replication(id: &VmId) -> Result<Option<Replication>> {
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop;
+            if ($vm.ReplicationState.ToString() -ne 'Disabled') {{
+                $vm | get-vmreplication -ErrorAction Stop | select-object -property @{{Name='Mode';Expression={{$_.Mode.ToString()}}}},\
+                    @{{Name='State';Expression={{$_.State.ToString()}}}},@{{Name='Health';Expression={{$_.Health.ToString()}}}},\
+                    PrimaryServer,ReplicaServer,FrequencySec,\
+                    @{{Name='LastReplicationTime';Expression={{ if ($_.LastReplicationTime) {{ ([DateTimeOffset]$_.LastReplicationTime).ToUnixTimeMilliseconds() }} else {{ $null }} }}}} |
+                    convertto-json
+            }}",
+            id);
+        let raw: Option<RawReplication> = Self::spawn_and_deserialize(&command)?;
+        raw.map(RawReplication::into_replication).transpose()
+    }
+
+    // Passing None for app_consistent_interval turns off VSS (application consistent) snapshots on the replica
+    pub fn set_replication_options(id: &VmId, frequency_secs: u32, recovery_points: u32, app_consistent_interval: Option<Duration>) -> Result<()> {
+        if !REPLICATION_FREQUENCIES_SECS.contains(&frequency_secs) {
+            return Err(HypervError::new(format!("Invalid replication frequency {}s. Must be one of 30, 300 or 900", frequency_secs)))
+        }
+
+        if recovery_points > MAX_RECOVERY_POINTS {
+            return Err(HypervError::new(format!("Invalid number of recovery points {}. Must not exceed {}", recovery_points, MAX_RECOVERY_POINTS)))
+        }
+
+        let vss_param = match app_consistent_interval {
+            Some(interval) => {
+                let hours = interval.as_secs() / 3600;
+                if interval.as_secs() % 3600 != 0 || hours == 0 || hours > MAX_APP_CONSISTENT_INTERVAL_HOURS {
+                    return Err(HypervError::new(format!("Invalid app consistent interval {:?}. Must be a whole number of hours between 1 and {}", interval, MAX_APP_CONSISTENT_INTERVAL_HOURS)))
+                }
+                if recovery_points == 0 {
+                    return Err(HypervError::new("App consistent snapshots require at least one additional recovery point"))
+                }
+                format!("-VSSSnapshotFrequencyHour {}", hours)
+            },
+            None => "-DisableVSSSnapshotReplication".to_owned(),
+        };
+
+        let command = format!(
+            "get-vm -Id '{}' -ErrorAction Stop | set-vmreplication -ReplicationFrequencySec {} -RecoveryHistory {} {} -ErrorAction Stop",
+            id,
+            frequency_secs,
+            recovery_points,
+            vss_param);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Returns the DNS name of the Replica Broker of the cluster the given host belongs to.
+    // None if the host is not clustered or its cluster has no broker configured
+    pub fn get_replica_broker(host: &str) -> Result<Option<String>> {
+        let command = format!(
+            "if (get-command get-clustergroup -ErrorAction SilentlyContinue) {{
+                $group = get-clustergroup -Cluster {} -ErrorAction SilentlyContinue | where-object {{ $_.GroupType -eq 'VMReplicaBroker' }} | select-object -First 1;
+                if ($group) {{
+                    $group | get-clusterresource | where-object {{ $_.ResourceType -eq 'Network Name' }} |
+                        get-clusterparameter -Name DnsName | foreach-object {{ $_.Value }} | convertto-json
+                }}
+            }}",
+            ps_quote(host));
+        Self::spawn_and_deserialize(&command)
+    }
+
+    pub fn resolve_replica_server(server: &str) -> Result<String> {
+        Ok(Self::get_replica_broker(server)?.unwrap_or_else(|| server.to_owned()))
+    }
+
+    // Must be called against the replica server. Most recent recovery point comes first
+    pub fn get_recovery_points(id: &VmId) -> Result<Vec<RecoveryPoint>> {
+        let command = format!(
+            "convertto-json -InputObject @(get-vm -Id '{}' -ErrorAction Stop | get-vmsnapshot -SnapshotType Replica |
+                sort-object -Property CreationTime -Descending |
+                select-object -property Id,Name,@{{Name='CreationTime';Expression={{([DateTimeOffset]$_.CreationTime).ToUnixTimeMilliseconds()}}}})",
+            id);
+        let points: Vec<RawRecoveryPoint> = Self::spawn_and_deserialize_list(&command)?;
+        Ok(points.into_iter().map(RecoveryPoint::from).collect())
+    }
+
+    // Unplanned failover of the replica VM, e.g. when the primary is lost. Without a recovery point the latest
+    // replicated state is used. Must be called against the replica server. Follow with complete_failover()
+    pub fn failover_vm(id: &VmId, recovery_point: Option<&RecoveryPoint>) -> Result<()> {
+        let command = format!(
+            "{}
+            start-vmfailover {} -Confirm:$false -ErrorAction Stop",
+            failover_source(id, recovery_point),
+            failover_target(recovery_point));
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Creates a test VM from the replica without disturbing replication, for DR drills. The test VM is not
+    // connected to any network until the caller connects it. Must be called against the replica server.
+    // Returns the test VM. Remove it with stop_test_failover()
+    pub fn start_test_failover(id: &VmId, recovery_point: Option<&RecoveryPoint>) -> Result<Vm> {
+        let command = format!(
+            "{}
+            start-vmfailover {} -AsTest -Passthru -Confirm:$false -ErrorAction Stop | select-object -property {} | convertto-json",
+            failover_source(id, recovery_point),
+            failover_target(recovery_point),
+            VmProp::projection_of(&VmProp::ALL));
+        let vm: Option<Vm> = Self::spawn_mutation_and_deserialize(&command)?;
+        vm.ok_or_else(|| HypervError::new("Start-VMFailover did not return the test VM"))
+    }
+
+    // Deletes the test VM of the replica VM id. Takes the replica, not the test VM
+    pub fn stop_test_failover(id: &VmId) -> Result<()> {
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | stop-vmfailover -Confirm:$false -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // First step of a planned failover, e.g. before maintenance of the primary site. Must be called against
+    // the primary server with the VM off. Sends the changes not replicated yet so no data is lost. Then
+    // fail over the replica with failover_vm() and complete_failover() on the replica server
+    pub fn start_planned_failover(id: &VmId) -> Result<()> {
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop;
+            if ($vm.State.ToString() -ne 'Off') {{ throw 'The VM must be off to prepare a planned failover' }}
+            $vm | start-vmfailover -Prepare -Confirm:$false -ErrorAction Stop",
+            id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Commits a failover on the replica server. Other recovery points are deleted and the failover can no
+    // longer be cancelled
+    pub fn complete_failover(id: &VmId) -> Result<()> {
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | complete-vmfailover -Confirm:$false -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+// Looks up the replica VM as $vm and, if a recovery point is given, it as $snapshot
+fn failover_source(id: &VmId, recovery_point: Option<&RecoveryPoint>) -> String {
+    match recovery_point {
+        Some(point) => format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop;
+            $snapshot = $vm | get-vmsnapshot -SnapshotType Replica | where-object {{ $_.Id -eq '{}' }};
+            if (-not $snapshot) {{ throw 'Recovery point not found' }}",
+            id,
+            point.id),
+        None => format!("$vm = get-vm -Id '{}' -ErrorAction Stop;", id),
+    }
+}
+
+fn failover_target(recovery_point: Option<&RecoveryPoint>) -> &'static str {
+    match recovery_point {
+        Some(_) => "-VMSnapshot $snapshot",
+        None => "-VM $vm",
+    }
+}
+
+fn validate_thumbprint(thumbprint: &str) -> Result<&str> {
+    let thumbprint = thumbprint.trim();
+    if thumbprint.len() != 40 || !thumbprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        Err(HypervError::new(format!("Invalid certificate thumbprint '{}'. Must be 40 hex digits", thumbprint)))
+    } else {
+        Ok(thumbprint)
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{Checkpoint, ErrorKind, Hyperv, HypervError, Result, StopMode, Vm, VmId, VmMetrics, VmProp, VmState};
+use serde_json::{json, Value};
+use std::time::UNIX_EPOCH;
+use tiny_http::{Header, Method, Request, Response};
+
+// A small HTTP/JSON front end to the crate so a host can be managed from machines that can't run
+// PowerShell. Requests are served one at a time on the thread that calls run(). Every request must carry
+// the token given to bind() as "Authorization: Bearer <token>" and is answered with 401 otherwise. The
+// server speaks plain HTTP, so the token and everything else travel unencrypted. Bind to anything but
+// loopback only on a trusted network or behind a TLS terminating proxy, since whoever holds the token can
+// start, stop and checkpoint every VM on the host
+pub struct Server {
+    http: tiny_http::Server,
+    token: String,
+}
+
+enum RouteError {
+    // The request itself is malformed, e.g. an invalid VM Id
+    BadRequest(String),
+    Failed(HypervError),
+}
+
+impl From<HypervError> for RouteError {
+    fn from(e: HypervError) -> Self {
+        RouteError::Failed(e)
+    }
+}
+
+impl Server {
+    // Listens on this machine's loopback interface only, so only local clients can reach the server
+    pub fn bind_local(port: u16, token: &str) -> Result<Self> {
+        Self::bind(&format!("127.0.0.1:{}", port), token)
+    }
+
+    pub fn bind(addr: &str, token: &str) -> Result<Self> {
+        if token.trim().is_empty() {
+            return Err(HypervError::new("The server token cannot be empty"))
+        }
+        let http = tiny_http::Server::http(addr)
+            .map_err(|e| HypervError::new(format!("Failed to listen on {}: {}", addr, e)))?;
+        Ok(Self { http, token: token.to_owned() })
+    }
+
+    // Serves requests until the listener fails. A response that can't be sent, e.g. because the client
+    // went away, only affects that request
+    pub fn run(&self) -> Result<()> {
+        for request in self.http.incoming_requests() {
+            let _ = self.handle(request);
+        }
+        Ok(())
+    }
+
+    fn handle(&self, request: Request) -> Result<()> {
+        let (status, body) = if !self.is_authorized(&request) {
+            (401, json!({ "error": "Unauthorized" }))
+        } else {
+            match split_path(request.url()).and_then(|segments| {
+                let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+                Self::route(request.method(), &segments)
+            }) {
+                Ok(Some(body)) => (200, body),
+                Ok(None) => (404, json!({ "error": "Not found" })),
+                Err(RouteError::BadRequest(msg)) => (400, json!({ "error": msg })),
+                Err(RouteError::Failed(ref e)) if e.kind == ErrorKind::NotFound => (404, json!({ "error": e.msg })),
+                Err(RouteError::Failed(e)) => (500, json!({ "error": e.msg })),
+            }
+        };
+
+        let mut response = Response::from_string(body.to_string()).with_status_code(status).with_header(header("Content-Type", "application/json")?);
+        if status == 401 {
+            response = response.with_header(header("WWW-Authenticate", "Bearer")?);
+        }
+        request.respond(response)
+            .map_err(|e| HypervError::new(format!("Failed to send HTTP response: {}", e)))
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        let expected = format!("Bearer {}", self.token);
+        request.headers().iter()
+            .filter(|h| h.field.equiv("Authorization"))
+            .any(|h| constant_time_eq(h.value.as_str().trim().as_bytes(), expected.as_bytes()))
+    }
+
+    // Returns None for unknown routes
+    fn route(method: &Method, segments: &[&str]) -> std::result::Result<Option<Value>, RouteError> {
+        match (method, segments) {
+            (Method::Get, ["vms"]) => {
+                let vms = Hyperv::get_vms_with(&VmProp::ALL)?;
+                Ok(Some(Value::Array(vms.iter().map(vm_to_json).collect())))
+            },
+            (Method::Get, ["vms", id]) => {
+                let id = parse_id(id)?;
+                Ok(Some(vm_to_json(&Hyperv::get_vm_by_id(&id)?)))
+            },
+            (Method::Get, ["vms", id, "checkpoints"]) => {
+                let checkpoints = Hyperv::list_checkpoints(&parse_id(id)?)?;
+                Ok(Some(Value::Array(checkpoints.iter().map(checkpoint_to_json).collect())))
+            },
+            (Method::Get, ["vms", id, "metrics"]) => {
+                let metrics = Hyperv::measure_vm(parse_id(id)?)?;
+                Ok(Some(metrics_to_json(&metrics)))
+            },
+            (Method::Post, ["vms", id, "checkpoints", name]) => {
+                let checkpoint = Hyperv::create_checkpoint(&parse_id(id)?, name)?;
+                Ok(Some(checkpoint_to_json(&checkpoint)))
+            },
+            (Method::Post, ["vms", id, action]) => {
+                let id = parse_id(id)?;
+                match *action {
+                    "start" => Hyperv::start_vm(id)?,
+                    "stop" => Hyperv::stop_vm(id, StopMode::Shutdown, None)?,
+                    "restart" => Hyperv::restart_vm(id)?,
+                    _ => return Ok(None),
+                }
+                Ok(Some(vm_to_json(&Hyperv::get_vm_by_id(&id)?)))
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+fn header(field: &str, value: &str) -> Result<Header> {
+    Header::from_bytes(field.as_bytes(), value.as_bytes())
+        .map_err(|_| HypervError::new(format!("Failed to build {} header", field)))
+}
+
+// Takes as long for every wrong token of the right length, so response times don't give the token away
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// The percent-decoded segments of the URL's path, without its query string or fragment
+fn split_path(url: &str) -> std::result::Result<Vec<String>, RouteError> {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    path.trim_matches('/').split('/').map(percent_decode).collect()
+}
+
+fn percent_decode(segment: &str) -> std::result::Result<String, RouteError> {
+    let invalid = || RouteError::BadRequest(format!("Invalid percent-encoding in '{}'", segment));
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).ok_or_else(invalid)?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+fn parse_id(id: &str) -> std::result::Result<VmId, RouteError> {
+    VmId::parse(id).map_err(|e| RouteError::BadRequest(e.msg))
+}
+
+fn vm_to_json(vm: &Vm) -> Value {
+    json!({
+        "id": vm.id.to_string(),
+        "name": vm.name,
+        "state": vm.state.as_ref().map(state_to_str),
+        "status": vm.status,
+        "uptime_secs": vm.uptime.map(|u| u.as_secs()),
+        "generation": vm.generation,
+        "processor_count": vm.processor_count,
+        "memory_assigned": vm.memory_assigned,
+        "version": vm.version,
+    })
+}
+
+fn checkpoint_to_json(checkpoint: &Checkpoint) -> Value {
+    json!({
+        "id": checkpoint.id.to_string(),
+        "vm_id": checkpoint.vm_id.to_string(),
+        "name": checkpoint.name,
+        "creation_time_ms": checkpoint.creation_time
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_millis())),
+    })
+}
+
+fn metrics_to_json(metrics: &VmMetrics) -> Value {
+    json!({
+        "avg_cpu_mhz": metrics.avg_cpu_mhz,
+        "avg_memory_mb": metrics.avg_memory_mb,
+        "min_memory_mb": metrics.min_memory_mb,
+        "max_memory_mb": metrics.max_memory_mb,
+        "total_disk_mb": metrics.total_disk_mb,
+        "network_inbound_mb": metrics.network_inbound_mb,
+        "network_outbound_mb": metrics.network_outbound_mb,
+        "metering_duration_secs": metrics.metering_duration.as_secs(),
+    })
+}
+
+fn state_to_str(state: &VmState) -> &str {
+    match state {
+        VmState::Running => "Running",
+        VmState::Off => "Off",
+        VmState::Starting => "Starting",
+        VmState::Stopping => "Stopping",
+        VmState::Saved => "Saved",
+        VmState::Saving => "Saving",
+        VmState::Paused => "Paused",
+        VmState::Pausing => "Pausing",
+        VmState::Resuming => "Resuming",
+        VmState::Reset => "Reset",
+        VmState::Other(s) => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(url: &str) -> Vec<String> {
+        split_path(url).unwrap_or_else(|_| panic!("failed to split {}", url))
+    }
+
+    #[test]
+    fn splits_decoded_path_without_query() {
+        assert_eq!(segments("/vms/1/checkpoints/a%20b?x=1"), vec!["vms", "1", "checkpoints", "a b"]);
+        assert_eq!(segments("/vms?x=1#top"), vec!["vms"]);
+        assert!(split_path("/vms/%zz").is_err());
+        assert!(split_path("/vms/%e2%28").is_err());
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(constant_time_eq(b"Bearer secret", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer secreT", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer", b"Bearer secret"));
+    }
+}
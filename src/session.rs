@@ -0,0 +1,165 @@
+use crate::{Hyperv, HypervError, Result};
+use crate::config::encode_command;
+use crate::credential::referenced_secrets;
+use crate::error_record;
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+thread_local! {
+    static CURRENT: RefCell<Option<HypervSession>> = const { RefCell::new(None) };
+}
+
+// Session used by threads not inside HypervSession::run(), if any
+static SHARED: Mutex<Option<HypervSession>> = Mutex::new(None);
+
+// A long-lived PowerShell process that operations are piped to, saving the second or more each new
+// process costs to start and load the Hyper-V module. Commands run one at a time, so a session suits
+// many short queries better than long operations like exports. Operations that hand a password to
+// PowerShell still start their own process since the session cannot see it. If the process dies the
+// next operation starts a new one
+#[derive(Clone)]
+pub struct HypervSession {
+    process: Arc<Mutex<Option<SessionProcess>>>,
+}
+
+struct SessionProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    // Ends the output of each command. Unique per process so no command output can be mistaken for it
+    marker: String,
+}
+
+impl HypervSession {
+    pub fn start() -> Result<Self> {
+        Ok(Self { process: Arc::new(Mutex::new(Some(SessionProcess::start()?))) })
+    }
+
+    // Runs f with every operation it performs on the calling thread going through this session
+    pub fn run<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let previous = CURRENT.with(|c| c.replace(Some(self.clone())));
+        let result = f();
+        CURRENT.with(|c| *c.borrow_mut() = previous);
+        result
+    }
+
+    pub(crate) fn execute(&self, command: &str) -> Result<String> {
+        let mut process = self.process.lock().unwrap_or_else(|e| e.into_inner());
+        if process.is_none() {
+            *process = Some(SessionProcess::start()?);
+        }
+        let result = match process.as_mut() {
+            Some(p) => p.execute(command),
+            None => return Err(HypervError::new("No PowerShell session")),
+        };
+        match result {
+            Ok(output) => Ok(output),
+            Err(SessionError::Failed(e)) => Err(e),
+            // A process that is out of step with us can't be used any more. Dropping it kills it
+            Err(SessionError::Broken(e)) => {
+                *process = None;
+                Err(e)
+            },
+        }
+    }
+}
+
+enum SessionError {
+    // The command failed. The session can go on
+    Failed(HypervError),
+    // Talking to the process failed. It must be replaced
+    Broken(HypervError),
+}
+
+impl From<HypervError> for SessionError {
+    fn from(e: HypervError) -> Self {
+        SessionError::Broken(e)
+    }
+}
+
+impl SessionProcess {
+    fn start() -> Result<Self> {
+        let mut child = Command::new("powershell.exe")
+            .args(["-NoProfile", "-NonInteractive", "-NoLogo", "-Command", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| HypervError::new(format!("Failed to spawn PowerShell process: {}", e)))?;
+        let stdin = child.stdin.take().ok_or_else(|| HypervError::new("Failed to obtain stdin of PowerShell process"))?;
+        let stdout = child.stdout.take().ok_or_else(|| HypervError::new("Failed to obtain stdout of PowerShell process"))?;
+        Ok(Self { child, stdin, stdout: BufReader::new(stdout), marker: format!("hyperv_rs-{}", Uuid::new_v4().to_simple()) })
+    }
+
+    // Each command is sent as a single line so PowerShell does not wait for more input to complete it,
+    // and runs in a child scope so its variables and functions do not leak into later commands
+    fn execute(&mut self, command: &str) -> std::result::Result<String, SessionError> {
+        let line = format!(
+            "$hyperv_rs_status = try {{ $hyperv_rs_output = & {{ invoke-expression ([Text.Encoding]::Unicode.GetString([Convert]::FromBase64String('{}'))) }} | out-string -Width 4096; 'OK' }} catch {{ $hyperv_rs_output = ($_ | out-string -Width 4096) + {}; 'ERR' }}; [Console]::Out.Write($hyperv_rs_output); [Console]::Out.WriteLine(''); [Console]::Out.WriteLine('{} ' + $hyperv_rs_status); [Console]::Out.Flush()\n",
+            encode_command(command),
+            error_record::RECORD_EXPRESSION,
+            self.marker);
+        self.stdin.write_all(line.as_bytes()).and_then(|_| self.stdin.flush())
+            .map_err(|e| HypervError::new(format!("Failed to send command to PowerShell session: {}", e)))?;
+
+        let mut output = String::new();
+        loop {
+            let mut line = String::new();
+            let read = self.stdout.read_line(&mut line)
+                .map_err(|e| HypervError::new(format!("Failed to read PowerShell session output: {}", e)))?;
+            if read == 0 {
+                return Err(SessionError::Broken(HypervError::new("PowerShell session exited unexpectedly")))
+            }
+            let trimmed = line.trim_end();
+            if trimmed.starts_with(&self.marker) {
+                return match &trimmed[self.marker.len()..] {
+                    " OK" => Ok(output),
+                    _ => Err(SessionError::Failed(match error_record::parse(&output) {
+                        Some(record) => HypervError::from_record(record),
+                        None => HypervError::new(format!("PowerShell command failed: {}", output.trim())),
+                    })),
+                }
+            }
+            output.push_str(&line);
+        }
+    }
+}
+
+impl Drop for SessionProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// The session operations on the calling thread go through, if any
+pub(crate) fn current_session() -> Option<HypervSession> {
+    CURRENT.with(|c| c.borrow().clone()).or_else(|| SHARED.lock().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+// For operations that spread their work over threads, which must stay in the caller's session
+pub(crate) fn with_session<T, F: FnOnce() -> T>(session: Option<&HypervSession>, f: F) -> T {
+    match session {
+        Some(session) => session.run(f),
+        None => f(),
+    }
+}
+
+// Commands reading a password from this process's environment can't, since the session's process was
+// started without it
+pub(crate) fn can_run(command: &str) -> bool {
+    referenced_secrets(command).is_empty()
+}
+
+impl Hyperv {
+    // Routes all subsequent operations, on every thread, through one shared session. Pass false to go
+    // back to a new process per operation
+    pub fn use_shared_session(enabled: bool) -> Result<()> {
+        let session = if enabled { Some(HypervSession::start()?) } else { None };
+        *SHARED.lock().unwrap_or_else(|e| e.into_inner()) = session;
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{Hyperv, HypervError, Result, VmId};
+use serde_derive::Deserialize;
+
+// The desired configuration of a VM. Properties left as None are not checked
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VmSpec {
+    pub generation: Option<u8>,
+    pub processor_count: Option<u32>,
+    pub memory_startup_bytes: Option<u64>,
+    pub dynamic_memory_enabled: Option<bool>,
+    // Names of the switches the VM's adapters connect to, in adapter order
+    pub switches: Option<Vec<String>>,
+}
+
+// A property whose live value deviates from the spec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Drift {
+    pub property: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Deserialize)]
+struct LiveConfig {
+    #[serde(rename = "Generation")]
+    generation: u8,
+    #[serde(rename = "ProcessorCount")]
+    processor_count: u32,
+    #[serde(rename = "MemoryStartup")]
+    memory_startup: u64,
+    #[serde(rename = "DynamicMemoryEnabled")]
+    dynamic_memory_enabled: bool,
+    #[serde(rename = "Switches")]
+    switches: Vec<Option<String>>,
+}
+
+impl Hyperv {
+    // Reports how the live VM differs from the spec. Nothing on the VM is changed
+    pub fn diff(id: &VmId, spec: &VmSpec) -> Result<Vec<Drift>> {
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop;
+            [pscustomobject]@{{
+                Generation = $vm.Generation;
+                ProcessorCount = $vm.ProcessorCount;
+                MemoryStartup = $vm.MemoryStartup;
+                DynamicMemoryEnabled = $vm.DynamicMemoryEnabled;
+                Switches = @($vm | get-vmnetworkadapter | foreach-object {{ $_.SwitchName }})
+            }} | convertto-json",
+            id);
+        let live: Option<LiveConfig> = Self::spawn_and_deserialize(&command)?;
+        let live = live.ok_or_else(|| HypervError::new("VM configuration query returned no output"))?;
+
+        let mut drifts = Vec::new();
+        check(&mut drifts, "Generation", spec.generation, live.generation);
+        check(&mut drifts, "ProcessorCount", spec.processor_count, live.processor_count);
+        check(&mut drifts, "MemoryStartup", spec.memory_startup_bytes, live.memory_startup);
+        check(&mut drifts, "DynamicMemoryEnabled", spec.dynamic_memory_enabled, live.dynamic_memory_enabled);
+
+        if let Some(expected) = &spec.switches {
+            let actual: Vec<String> = live.switches.into_iter().map(|s| s.unwrap_or_default()).collect();
+            if *expected != actual {
+                drifts.push(Drift { property: "Switches", expected: expected.join(","), actual: actual.join(",") });
+            }
+        }
+
+        Ok(drifts)
+    }
+}
+
+fn check<T: PartialEq + ToString>(drifts: &mut Vec<Drift>, property: &'static str, expected: Option<T>, actual: T) {
+    if let Some(expected) = expected {
+        if expected != actual {
+            drifts.push(Drift { property, expected: expected.to_string(), actual: actual.to_string() });
+        }
+    }
+}
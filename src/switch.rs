@@ -0,0 +1,143 @@
+use crate::{Hyperv, HypervError, Result, VmId, ps_quote};
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+// The built-in "Default Switch" of client SKUs always has this Id. Its name is localized, so it
+// must not be looked up by name
+pub const DEFAULT_SWITCH_ID: &str = "c08cb7b8-9b3c-408e-8e30-5e16a3aeb444";
+
+impl Hyperv {
+    // Returns the (possibly localized) name of the Default Switch. None on server SKUs and older
+    // client versions which don't have one
+    pub fn get_default_switch_name() -> Result<Option<String>> {
+        let command = format!(
+            "get-vmswitch -Id '{}' -ErrorAction SilentlyContinue | foreach-object {{ $_.Name }} | convertto-json",
+            DEFAULT_SWITCH_ID);
+        Self::spawn_and_deserialize(&command)
+    }
+
+    // Connects the named adapter of the VM, or all of its adapters if no name is given, to the Default Switch
+    pub fn connect_to_default_switch(id: &VmId, adapter_name: Option<&str>) -> Result<()> {
+        if Self::get_default_switch_name()?.is_none() {
+            return Err(HypervError::new("This host has no Default Switch. It is only available on client versions of Windows"))
+        }
+
+        let adapter_filter = match adapter_name {
+            Some(name) => format!("-Name {}", ps_quote(name)),
+            None => "".to_owned(),
+        };
+        let command = format!(
+            "$switch = get-vmswitch -Id '{}' -ErrorAction Stop;
+            get-vm -Id '{}' -ErrorAction Stop | get-vmnetworkadapter {} -ErrorAction Stop | connect-vmnetworkadapter -VMSwitch $switch -ErrorAction Stop",
+            DEFAULT_SWITCH_ID,
+            id,
+            adapter_filter);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchType {
+    // Bound to a physical adapter, so VMs reach the physical network
+    External,
+    // VMs reach each other and the host
+    Internal,
+    // VMs only reach each other
+    Private,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VSwitch {
+    pub id: Uuid,
+    pub name: String,
+    pub switch_type: SwitchType,
+    // Description of the physical adapter an external switch is bound to
+    pub uplink: Option<String>,
+    pub iov_enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct RawVSwitch {
+    #[serde(rename = "Id")]
+    id: Uuid,
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "SwitchType")]
+    switch_type: String,
+    #[serde(rename = "NetAdapterInterfaceDescription")]
+    uplink: Option<String>,
+    #[serde(rename = "IovEnabled")]
+    iov_enabled: bool,
+}
+
+impl RawVSwitch {
+    fn into_switch(self) -> Result<VSwitch> {
+        let switch_type = match self.switch_type.as_str() {
+            "External" => SwitchType::External,
+            "Internal" => SwitchType::Internal,
+            "Private" => SwitchType::Private,
+            t => return Err(HypervError::new(format!("Unexpected switch type '{}'", t))),
+        };
+        Ok(VSwitch {
+            id: self.id,
+            name: self.name,
+            switch_type,
+            uplink: self.uplink.filter(|u| !u.is_empty()),
+            iov_enabled: self.iov_enabled,
+        })
+    }
+}
+
+const SWITCH_PROJECTION: &str = "@{Name='Id';Expression={$_.Id.ToString()}},Name,@{Name='SwitchType';Expression={$_.SwitchType.ToString()}},NetAdapterInterfaceDescription,IovEnabled";
+
+impl VSwitch {
+    pub fn list() -> Result<Vec<VSwitch>> {
+        let command = format!("convertto-json -InputObject @(get-vmswitch | select-object -property {})", SWITCH_PROJECTION);
+        let raw: Vec<RawVSwitch> = Hyperv::spawn_and_deserialize_list(&command)?;
+        raw.into_iter().map(RawVSwitch::into_switch).collect()
+    }
+
+    // Binds the switch to the named physical adapter (as shown by Get-NetAdapter). If allow_management_os is
+    // set the host keeps using the adapter through a virtual one. Otherwise the host loses its connectivity
+    // through it, which will cut off a remote session running over that adapter
+    pub fn create_external(name: &str, net_adapter_name: &str, allow_management_os: bool) -> Result<VSwitch> {
+        Self::create(&format!(
+            "-Name {} -NetAdapterName {} -AllowManagementOS ${}",
+            ps_quote(name),
+            ps_quote(net_adapter_name),
+            allow_management_os))
+    }
+
+    // Like create_external() but lets VM adapters with an IOV weight bypass the switch and use virtual
+    // functions of the physical adapter directly. SR-IOV can only be turned on when a switch is created.
+    // Check Hyperv::get_iov_support() first
+    pub fn create_external_iov(name: &str, net_adapter_name: &str, allow_management_os: bool) -> Result<VSwitch> {
+        Self::create(&format!(
+            "-Name {} -NetAdapterName {} -AllowManagementOS ${} -EnableIov $true",
+            ps_quote(name),
+            ps_quote(net_adapter_name),
+            allow_management_os))
+    }
+
+    pub fn create_internal(name: &str) -> Result<VSwitch> {
+        Self::create(&format!("-Name {} -SwitchType Internal", ps_quote(name)))
+    }
+
+    pub fn create_private(name: &str) -> Result<VSwitch> {
+        Self::create(&format!("-Name {} -SwitchType Private", ps_quote(name)))
+    }
+
+    // Adapters connected to the switch are left disconnected
+    pub fn remove(self) -> Result<()> {
+        let command = format!("get-vmswitch -Id '{}' -ErrorAction Stop | remove-vmswitch -Force -ErrorAction Stop", self.id);
+        Hyperv::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    fn create(params: &str) -> Result<VSwitch> {
+        let command = format!("new-vmswitch {} -ErrorAction Stop | select-object -property {} | convertto-json", params, SWITCH_PROJECTION);
+        let raw: Option<RawVSwitch> = Hyperv::spawn_mutation_and_deserialize(&command)?;
+        raw.ok_or_else(|| HypervError::new("New-VMSwitch did not return the created switch"))?.into_switch()
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{Hyperv, HypervError, Result, Vm, VmId, ps_quote};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+
+// Tags live in the VM's Notes field on a line of their own, after this marker, so that any notes
+// a human has written there are preserved
+const TAGS_MARKER: &str = "hyperv_rs-tags:";
+
+#[derive(Deserialize)]
+struct NotedVm {
+    #[serde(flatten)]
+    vm: Vm,
+    #[serde(rename = "Notes", default)]
+    notes: Option<String>,
+}
+
+impl Hyperv {
+    pub fn get_tags(id: &VmId) -> Result<HashMap<String, String>> {
+        let notes = Self::get_notes(id)?;
+        parse_tags(notes.as_deref().unwrap_or(""))
+    }
+
+    // Replaces all existing tags of the VM. An empty map removes the tags altogether
+    pub fn set_tags(id: &VmId, tags: &HashMap<String, String>) -> Result<()> {
+        let notes = Self::get_notes(id)?.unwrap_or_default();
+        let mut lines: Vec<&str> = notes.lines().filter(|l| !l.starts_with(TAGS_MARKER)).collect();
+
+        let tag_line;
+        if !tags.is_empty() {
+            let json = serde_json::to_string(tags)
+                .map_err(|e| HypervError::new(format!("Failed to serialize tags: {}", e)))?;
+            tag_line = format!("{}{}", TAGS_MARKER, json);
+            lines.push(&tag_line);
+        }
+
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | set-vm -Notes {} -ErrorAction Stop", id, ps_quote(&lines.join("\n")));
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Returns VMs having the given tag. Passing None for the value matches any value
+    pub fn find_vms_by_tag(key: &str, value: Option<&str>) -> Result<Vec<Vm>> {
+        let command = "convertto-json -InputObject @(get-vm | select-object -property Id,Name,Notes)";
+        let vms: Vec<NotedVm> = Self::spawn_and_deserialize_list(command)?;
+
+        let mut found = Vec::new();
+        for noted in vms {
+            let tags = parse_tags(noted.notes.as_deref().unwrap_or(""))?;
+            let matches = match (tags.get(key), value) {
+                (Some(v), Some(value)) => v == value,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            if matches {
+                found.push(noted.vm);
+            }
+        }
+
+        Ok(found)
+    }
+
+    fn get_notes(id: &VmId) -> Result<Option<String>> {
+        let command = format!("(get-vm -Id '{}' -ErrorAction Stop).Notes | convertto-json", id);
+        Self::spawn_and_deserialize(&command)
+    }
+}
+
+fn parse_tags(notes: &str) -> Result<HashMap<String, String>> {
+    match notes.lines().find(|l| l.starts_with(TAGS_MARKER)) {
+        Some(line) => serde_json::from_str(&line[TAGS_MARKER.len()..])
+            .map_err(|e| HypervError::new(format!("Failed to parse tags in VM notes: {}", e))),
+        None => Ok(HashMap::new()),
+    }
+}
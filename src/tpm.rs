@@ -0,0 +1,47 @@
+use crate::{Hyperv, HypervError, Result, VmId};
+
+// Get-VMKeyProtector returns a 4 byte placeholder when the VM has no key protector
+const EMPTY_KEY_PROTECTOR_LEN: u32 = 4;
+
+impl Hyperv {
+    // Gives the VM a virtual TPM, e.g. for Windows 11 guests or BitLocker testing. A VM needs a key protector
+    // for that. If it has none, one is created that is backed by the host's local "UntrustedGuardian",
+    // which Hyper-V creates on first use. Without a Host Guardian Service such a VM can only run on hosts
+    // that have that guardian's certificates. Generation 2 only and the VM must be off
+    pub fn enable_tpm(id: &VmId) -> Result<()> {
+        Self::require_generation_2(id)?;
+        let command = format!(
+            "$vm = get-vm -Id '{}' -ErrorAction Stop;
+            if (@($vm | get-vmkeyprotector -ErrorAction Stop).Length -le {}) {{
+                $vm | set-vmkeyprotector -NewLocalKeyProtector -ErrorAction Stop
+            }}
+            $vm | enable-vmtpm -ErrorAction Stop",
+            id,
+            EMPTY_KEY_PROTECTOR_LEN);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // The key protector is kept so the TPM can be enabled again with its previous contents
+    pub fn disable_tpm(id: &VmId) -> Result<()> {
+        Self::require_generation_2(id)?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | disable-vmtpm -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    pub fn is_tpm_enabled(id: &VmId) -> Result<bool> {
+        let command = format!("(get-vm -Id '{}' -ErrorAction Stop | get-vmsecurity -ErrorAction Stop).TpmEnabled | convertto-json", id);
+        let enabled: Option<bool> = Self::spawn_and_deserialize(&command)?;
+        enabled.ok_or_else(|| HypervError::new("Get-VMSecurity returned no output"))
+    }
+
+    // Replaces the VM's key protector with a new local one. Anything sealed to the old TPM, like BitLocker
+    // keys, becomes unreadable, so this is only for VMs whose key protector has been lost
+    pub fn set_local_key_protector(id: &VmId) -> Result<()> {
+        Self::require_generation_2(id)?;
+        let command = format!("get-vm -Id '{}' -ErrorAction Stop | set-vmkeyprotector -NewLocalKeyProtector -ErrorAction Stop", id);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
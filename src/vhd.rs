@@ -0,0 +1,548 @@
+use crate::{Hyperv, HypervError, Result, ps_quote};
+use crate::{progress, remote};
+use serde_derive::Deserialize;
+use std::path::{Path, PathBuf};
+
+const MB: u32 = 1024 * 1024;
+const MIN_DISK_SIZE: u64 = 3 * MB as u64;
+const MAX_VHD_SIZE: u64 = 2040 * 1024 * MB as u64;
+const MAX_VHDX_SIZE: u64 = 64 * 1024 * 1024 * MB as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VhdType {
+    Fixed,
+    Dynamic,
+    // Only ever reported by Vhd::inspect(). Create these with Vhd::create_differencing()
+    Differencing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VhdFormat {
+    // Legacy format. Up to 2040 GB and 512 byte sectors
+    Vhd,
+    Vhdx,
+}
+
+// How Optimize-VHD reclaims unused space of a dynamic or differencing disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptimizeMode {
+    // Scans for zero blocks as well as reclaiming unused ones. Disk must not be attached
+    Full,
+    // Reclaims unused blocks without scanning for zero blocks. Disk must be attached read-only
+    Quick,
+    // Sends trims for unused blocks to the host storage without reclaiming them
+    Retrim,
+    // Like Quick but relies on earlier trims rather than scanning the file system. VHDX only
+    Pretrimmed,
+}
+
+impl OptimizeMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OptimizeMode::Full => "Full",
+            OptimizeMode::Quick => "Quick",
+            OptimizeMode::Retrim => "Retrim",
+            OptimizeMode::Pretrimmed => "Pretrimmed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectorSize {
+    Bytes512,
+    // Needed for 4K native workloads. VHDX only
+    Bytes4096,
+}
+
+impl SectorSize {
+    fn bytes(&self) -> u32 {
+        match self {
+            SectorSize::Bytes512 => 512,
+            SectorSize::Bytes4096 => 4096,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NewVhdOptions {
+    vhd_type: VhdType,
+    logical_sector_size: Option<SectorSize>,
+    physical_sector_size: Option<SectorSize>,
+    block_size_bytes: Option<u32>,
+}
+
+impl NewVhdOptions {
+    pub fn new(vhd_type: VhdType) -> Self {
+        Self { vhd_type, logical_sector_size: None, physical_sector_size: None, block_size_bytes: None }
+    }
+
+    pub fn logical_sector_size(mut self, size: SectorSize) -> Self {
+        self.logical_sector_size = Some(size);
+        self
+    }
+
+    pub fn physical_sector_size(mut self, size: SectorSize) -> Self {
+        self.physical_sector_size = Some(size);
+        self
+    }
+
+    // Must be a power of two between 1 MB and 256 MB for VHDX, and 512 KB or 2 MB for VHD
+    pub fn block_size_bytes(mut self, size: u32) -> Self {
+        self.block_size_bytes = Some(size);
+        self
+    }
+
+    fn params(&self, vhdx: bool) -> Result<String> {
+        let mut params = match self.vhd_type {
+            VhdType::Fixed => "-Fixed".to_owned(),
+            VhdType::Dynamic => "-Dynamic".to_owned(),
+            VhdType::Differencing => return Err(HypervError::new("Differencing disks need a parent. Use Vhd::create_differencing()")),
+        };
+
+        if let Some(size) = self.logical_sector_size {
+            if !vhdx && size != SectorSize::Bytes512 {
+                return Err(HypervError::new("VHD files only support 512 byte logical sectors. Use VHDX for 4K sectors"))
+            }
+            params.push_str(&format!(" -LogicalSectorSizeBytes {}", size.bytes()));
+        }
+
+        if let Some(size) = self.physical_sector_size {
+            params.push_str(&format!(" -PhysicalSectorSizeBytes {}", size.bytes()));
+        }
+
+        if let Some(size) = self.block_size_bytes {
+            let valid = if vhdx {
+                size.is_power_of_two() && (MB..=256 * MB).contains(&size)
+            } else {
+                size == 512 * 1024 || size == 2 * MB
+            };
+            if !valid {
+                return Err(HypervError::new(format!("Invalid block size {} bytes for a {} file", size, if vhdx { "VHDX" } else { "VHD" })))
+            }
+            params.push_str(&format!(" -BlockSizeBytes {}", size));
+        }
+
+        Ok(params)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VhdInfo {
+    pub path: PathBuf,
+    pub format: VhdFormat,
+    pub vhd_type: VhdType,
+    // Virtual size as seen by the guest
+    pub size: u64,
+    // Space the file takes on the host
+    pub file_size: u64,
+    // See Vhd::minimum_size()
+    pub minimum_size: Option<u64>,
+    pub parent_path: Option<PathBuf>,
+    pub block_size: u32,
+    pub logical_sector_size: u32,
+    pub physical_sector_size: u32,
+    // Whether the disk is attached to the host or to a running VM
+    pub attached: bool,
+}
+
+#[derive(Deserialize)]
+struct RawVhdInfo {
+    #[serde(rename = "Path")]
+    path: PathBuf,
+    #[serde(rename = "VhdFormat")]
+    format: String,
+    #[serde(rename = "VhdType")]
+    vhd_type: String,
+    #[serde(rename = "Size")]
+    size: u64,
+    #[serde(rename = "FileSize")]
+    file_size: u64,
+    #[serde(rename = "MinimumSize")]
+    minimum_size: Option<u64>,
+    #[serde(rename = "ParentPath")]
+    parent_path: Option<String>,
+    #[serde(rename = "BlockSize")]
+    block_size: u32,
+    #[serde(rename = "LogicalSectorSize")]
+    logical_sector_size: u32,
+    #[serde(rename = "PhysicalSectorSize")]
+    physical_sector_size: u32,
+    #[serde(rename = "Attached")]
+    attached: bool,
+}
+
+impl RawVhdInfo {
+    fn into_info(self) -> Result<VhdInfo> {
+        let format = match self.format.as_str() {
+            "VHD" => VhdFormat::Vhd,
+            "VHDX" => VhdFormat::Vhdx,
+            f => return Err(HypervError::new(format!("Unsupported disk format '{}'", f))),
+        };
+        let vhd_type = match self.vhd_type.as_str() {
+            "Fixed" => VhdType::Fixed,
+            "Dynamic" => VhdType::Dynamic,
+            "Differencing" => VhdType::Differencing,
+            t => return Err(HypervError::new(format!("Unexpected disk type '{}'", t))),
+        };
+
+        Ok(VhdInfo {
+            path: self.path,
+            format,
+            vhd_type,
+            size: self.size,
+            file_size: self.file_size,
+            minimum_size: self.minimum_size,
+            parent_path: self.parent_path.filter(|p| !p.is_empty()).map(PathBuf::from),
+            block_size: self.block_size,
+            logical_sector_size: self.logical_sector_size,
+            physical_sector_size: self.physical_sector_size,
+            attached: self.attached,
+        })
+    }
+}
+
+const VHD_INFO_PROJECTION: &str = "Path,@{Name='VhdFormat';Expression={$_.VhdFormat.ToString()}},@{Name='VhdType';Expression={$_.VhdType.ToString()}},\
+    Size,FileSize,MinimumSize,ParentPath,BlockSize,LogicalSectorSize,PhysicalSectorSize,Attached";
+
+// One disk in a differencing chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VhdChainLink {
+    pub path: PathBuf,
+    // Sizes are None when the file is missing
+    pub file_size: Option<u64>,
+    pub size: Option<u64>,
+    // The file this link's child points to does not exist, so the chain is broken here
+    pub missing: bool,
+}
+
+#[derive(Deserialize)]
+struct RawVhdChainLink {
+    #[serde(rename = "Path")]
+    path: PathBuf,
+    #[serde(rename = "FileSize")]
+    file_size: Option<u64>,
+    #[serde(rename = "Size")]
+    size: Option<u64>,
+    #[serde(rename = "Exists")]
+    exists: bool,
+}
+
+// Guards against cycles in a corrupt chain
+const MAX_CHAIN_DEPTH: u32 = 256;
+
+impl Hyperv {
+    // The format (VHD or VHDX) is chosen by New-VHD from the file extension
+    pub fn new_vhd<P: AsRef<Path>>(path: P, size_bytes: u64, options: &NewVhdOptions) -> Result<()> {
+        let path = path.as_ref();
+        let vhdx = is_vhdx(path)?;
+        validate_size(size_bytes, vhdx)?;
+        let command = format!("new-vhd -Path {} -SizeBytes {} {} -ErrorAction Stop", quoted_path(path)?, size_bytes, options.params(vhdx)?);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Follows ParentPath links from the given disk down to its base disk. The first element is the
+    // given disk and the last is the base, or the first missing parent if the chain is broken
+    pub fn get_vhd_chain<P: AsRef<Path>>(path: P) -> Result<Vec<VhdChainLink>> {
+        let command = format!(
+            "$path = {}; $chain = @(); $depth = 0;
+            while ($path -and $depth -lt {}) {{
+                $depth++;
+                if (-not (test-path -LiteralPath $path)) {{
+                    $chain += [pscustomobject]@{{ Path = $path; FileSize = $null; Size = $null; Exists = $false }};
+                    break
+                }}
+                $vhd = get-vhd -Path $path -ErrorAction Stop;
+                $chain += [pscustomobject]@{{ Path = $vhd.Path; FileSize = $vhd.FileSize; Size = $vhd.Size; Exists = $true }};
+                $path = $vhd.ParentPath
+            }}
+            convertto-json -InputObject @($chain)",
+            quoted_path(path.as_ref())?,
+            MAX_CHAIN_DEPTH);
+        let raw: Vec<RawVhdChainLink> = Self::spawn_and_deserialize_list(&command)?;
+        Ok(raw.into_iter()
+            .map(|l| VhdChainLink { path: l.path, file_size: l.file_size, size: l.size, missing: !l.exists })
+            .collect())
+    }
+
+    // Points a differencing disk at a new parent, e.g. after its golden image has been moved.
+    // ignore_id_mismatch skips the check that the new parent is the same disk the child was created from,
+    // which risks silent corruption if it is not
+    pub fn set_vhd_parent<P: AsRef<Path>, Q: AsRef<Path>>(child: P, new_parent: Q, ignore_id_mismatch: bool) -> Result<()> {
+        let command = format!(
+            "set-vhd -Path {} -ParentPath {} {} -ErrorAction Stop",
+            quoted_path(child.as_ref())?,
+            quoted_path(new_parent.as_ref())?,
+            if ignore_id_mismatch { "-IgnoreIdMismatch" } else { "" });
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Mounts the disk, finds its Windows partition and copies each host file or directory to the given
+    // path relative to that partition's root (e.g. "Windows\Setup\Scripts\SetupComplete.cmd").
+    // The disk is always dismounted afterwards, even if a copy fails. It must not be attached to a running VM
+    pub fn copy_into_vhd<P: AsRef<Path>>(vhd: P, files: &[(&Path, &str)]) -> Result<()> {
+        Self::require_elevation()?;
+        let vhd = vhd.as_ref().to_str().ok_or_else(|| HypervError::new("Bad path"))?;
+        let mut copies = String::new();
+        for (src, dest) in files {
+            let src = src.to_str().ok_or_else(|| HypervError::new("Bad path"))?;
+            copies.push_str(&format!(
+                "$dest = join-path $root {};
+                new-item -ItemType Directory -Force -Path (split-path -Parent $dest) | out-null;
+                copy-item -Path {} -Destination $dest -Recurse -Force -ErrorAction Stop;
+                ",
+                ps_quote(dest.trim_start_matches('\\')),
+                ps_quote(src)));
+        }
+
+        let command = format!(
+            "$vhd = {};
+            $disk = mount-vhd -Path $vhd -Passthru -ErrorAction Stop | get-disk;
+            try {{
+                $disk | get-partition | where-object {{ -not $_.DriveLetter -and $_.Type -eq 'Basic' }} |
+                    add-partitionaccesspath -AssignDriveLetter -ErrorAction SilentlyContinue;
+                $root = $disk | get-partition | where-object {{ $_.DriveLetter }} | foreach-object {{ \"$($_.DriveLetter):\\\" }} |
+                    where-object {{ test-path (join-path $_ 'Windows\\System32') }} | select-object -First 1;
+                if (-not $root) {{ throw 'No Windows partition found on the disk' }}
+                {}
+            }} finally {{
+                dismount-vhd -Path $vhd
+            }}",
+            ps_quote(vhd),
+            copies);
+        Self::spawn_mutation(&command)?;
+        Ok(())
+    }
+}
+
+// Creates disk files. The format, VHD or VHDX, follows from the file extension
+pub struct Vhd;
+
+impl Vhd {
+    // Allocates the full size up front. Slower to create but avoids fragmentation and expansion overhead
+    pub fn create_fixed<P: AsRef<Path>>(path: P, size_bytes: u64) -> Result<()> {
+        Hyperv::new_vhd(path, size_bytes, &NewVhdOptions::new(VhdType::Fixed))
+    }
+
+    // Grows as data is written, up to size_bytes
+    pub fn create_dynamic<P: AsRef<Path>>(path: P, size_bytes: u64) -> Result<()> {
+        Hyperv::new_vhd(path, size_bytes, &NewVhdOptions::new(VhdType::Dynamic))
+    }
+
+    // Creates a disk recording only the changes made on top of parent, which must not be written to afterwards.
+    // The child must be of the same format as its parent
+    pub fn create_differencing<P: AsRef<Path>, Q: AsRef<Path>>(path: P, parent: Q) -> Result<()> {
+        let (path, parent) = (path.as_ref(), parent.as_ref());
+        if is_vhdx(path)? != is_vhdx(parent)? {
+            return Err(HypervError::new("A differencing disk must be of the same format (VHD or VHDX) as its parent"))
+        }
+
+        let command = format!(
+            "new-vhd -Path {} -ParentPath {} -Differencing -ErrorAction Stop",
+            quoted_path(path)?,
+            quoted_path(parent)?);
+        Hyperv::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // The smallest size the disk can be shrunk to without cutting into its partitions. None if Hyper-V
+    // cannot tell, e.g. for VHD files or disks whose partitions it does not understand
+    pub fn minimum_size<P: AsRef<Path>>(path: P) -> Result<Option<u64>> {
+        Ok(Self::get_sizes(path.as_ref())?.minimum_size)
+    }
+
+    // Grows or shrinks the disk. Shrinking below minimum_size(), or when it is unknown, is refused unless
+    // force is set, in which case Resize-VHD itself has the final say. The disk must not be in use
+    pub fn resize<P: AsRef<Path>>(path: P, new_size_bytes: u64, force: bool) -> Result<()> {
+        let path = path.as_ref();
+        validate_size(new_size_bytes, is_vhdx(path)?)?;
+
+        let sizes = Self::get_sizes(path)?;
+        if new_size_bytes < sizes.size && !force {
+            match sizes.minimum_size {
+                Some(min) if new_size_bytes < min => return Err(HypervError::new(format!(
+                    "Cannot shrink the disk to {} bytes. Its partitions need at least {} bytes", new_size_bytes, min))),
+                None => return Err(HypervError::new("The minimum size of the disk is unknown so it cannot be shrunk safely")),
+                _ => {},
+            }
+        }
+
+        let command = format!(
+            "resize-vhd -Path {} -SizeBytes {} -ErrorAction Stop",
+            quoted_path(path)?,
+            new_size_bytes);
+        Hyperv::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    // Copies source into a new disk of the given format and type, e.g. to move an old VHD to VHDX.
+    // Since Hyper-V picks the format from the file extension, dest must end in the matching .vhd or .vhdx.
+    // The source must not be in use
+    pub fn convert<P: AsRef<Path>, Q: AsRef<Path>>(source: P, dest: Q, format: VhdFormat, vhd_type: VhdType) -> Result<()> {
+        let (source, dest) = (source.as_ref(), dest.as_ref());
+        if is_vhdx(dest)? != (format == VhdFormat::Vhdx) {
+            return Err(HypervError::new(format!("Destination '{}' does not have the extension for {:?} format", dest.display(), format)))
+        }
+        if remote::is_local() && dest.exists() {
+            return Err(HypervError::new(format!("Destination '{}' already exists", dest.display())))
+        }
+
+        let command = format!(
+            "convert-vhd -Path {} -DestinationPath {} -VHDType {} -ErrorAction Stop",
+            quoted_path(source)?,
+            quoted_path(dest)?,
+            match vhd_type {
+                VhdType::Fixed => "Fixed",
+                VhdType::Dynamic => "Dynamic",
+                VhdType::Differencing => return Err(HypervError::new("Disks can only be converted to fixed or dynamic ones")),
+            });
+        Hyperv::spawn_mutation(&progress::tracked(&command))?;
+        Ok(())
+    }
+
+    // Shrinks the file of a dynamic or differencing disk by giving back space the guest no longer uses
+    pub fn optimize<P: AsRef<Path>>(path: P, mode: OptimizeMode) -> Result<()> {
+        let path = path.as_ref();
+        if mode == OptimizeMode::Pretrimmed && !is_vhdx(path)? {
+            return Err(HypervError::new("Pretrimmed optimization is only supported for VHDX files"))
+        }
+
+        let command = format!(
+            "optimize-vhd -Path {} -Mode {} -ErrorAction Stop",
+            quoted_path(path)?,
+            mode.as_str());
+        Hyperv::spawn_mutation(&progress::tracked(&command))?;
+        Ok(())
+    }
+
+    // Merges child and every disk between it and the ancestor into the ancestor, deleting the merged files.
+    // Any other differencing disk based on the ancestor or the merged disks is invalidated by this
+    pub fn merge<P: AsRef<Path>, Q: AsRef<Path>>(child: P, ancestor: Q) -> Result<()> {
+        let (child, ancestor) = (child.as_ref(), ancestor.as_ref());
+        let chain = Hyperv::get_vhd_chain(child)?;
+        let is_ancestor = chain.iter().skip(1).any(|link| !link.missing && same_path(&link.path, ancestor));
+        if !is_ancestor {
+            return Err(HypervError::new(format!("'{}' is not a parent of '{}'", ancestor.display(), child.display())))
+        }
+        Self::merge_into(child, ancestor)
+    }
+
+    // Consolidates the whole differencing chain, e.g. the .avhdx files left behind by checkpoints, into its
+    // base disk and returns the base's path
+    pub fn merge_chain<P: AsRef<Path>>(child: P) -> Result<PathBuf> {
+        let child = child.as_ref();
+        let chain = Hyperv::get_vhd_chain(child)?;
+        if chain.iter().any(|link| link.missing) {
+            return Err(HypervError::new(format!("The differencing chain of '{}' is broken", child.display())))
+        }
+
+        let base = match chain.last() {
+            Some(base) if chain.len() > 1 => base.path.clone(),
+            _ => return Err(HypervError::new(format!("'{}' is not a differencing disk", child.display()))),
+        };
+        Self::merge_into(child, &base)?;
+        Ok(base)
+    }
+
+    fn merge_into(child: &Path, ancestor: &Path) -> Result<()> {
+        let command = format!(
+            "merge-vhd -Path {} -DestinationPath {} -ErrorAction Stop",
+            quoted_path(child)?,
+            quoted_path(ancestor)?);
+        Hyperv::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    pub fn inspect<P: AsRef<Path>>(path: P) -> Result<VhdInfo> {
+        let command = format!(
+            "get-vhd -Path {} -ErrorAction Stop | select-object -property {} | convertto-json",
+            quoted_path(path.as_ref())?,
+            VHD_INFO_PROJECTION);
+        let raw: Option<RawVhdInfo> = Hyperv::spawn_and_deserialize(&command)?;
+        raw.ok_or_else(|| HypervError::new("Get-VHD returned no output"))?.into_info()
+    }
+
+    // Attaches the disk to the host and returns its disk number, e.g. to inspect or inject files offline.
+    // The disk must not be attached to a running VM. Requires an elevated process
+    pub fn mount<P: AsRef<Path>>(path: P, read_only: bool) -> Result<u32> {
+        Hyperv::require_elevation()?;
+        let command = format!(
+            "(mount-vhd -Path {} {} -Passthru -ErrorAction Stop | get-disk -ErrorAction Stop).Number | convertto-json",
+            quoted_path(path.as_ref())?,
+            if read_only { "-ReadOnly" } else { "" });
+        let number: Option<u32> = Hyperv::spawn_mutation_and_deserialize(&command)?;
+        number.ok_or_else(|| HypervError::new("Mount-VHD did not return the mounted disk"))
+    }
+
+    pub fn dismount<P: AsRef<Path>>(path: P) -> Result<()> {
+        Hyperv::require_elevation()?;
+        let command = format!(
+            "dismount-vhd -Path {} -ErrorAction Stop",
+            quoted_path(path.as_ref())?);
+        Hyperv::spawn_mutation(&command)?;
+        Ok(())
+    }
+
+    fn get_sizes(path: &Path) -> Result<VhdSizes> {
+        let command = format!(
+            "get-vhd -Path {} -ErrorAction Stop | select-object -property Size,MinimumSize | convertto-json",
+            quoted_path(path)?);
+        let sizes: Option<VhdSizes> = Hyperv::spawn_and_deserialize(&command)?;
+        sizes.ok_or_else(|| HypervError::new("Get-VHD returned no output"))
+    }
+}
+
+#[derive(Deserialize)]
+struct VhdSizes {
+    #[serde(rename = "Size")]
+    size: u64,
+    #[serde(rename = "MinimumSize")]
+    minimum_size: Option<u64>,
+}
+
+fn validate_size(size_bytes: u64, vhdx: bool) -> Result<()> {
+    let max = if vhdx { MAX_VHDX_SIZE } else { MAX_VHD_SIZE };
+    if size_bytes < MIN_DISK_SIZE || size_bytes > max || !size_bytes.is_multiple_of(512) {
+        return Err(HypervError::new(format!(
+            "Invalid disk size {} bytes. Must be a multiple of 512 between 3 MB and {} for a {} file",
+            size_bytes,
+            if vhdx { "64 TB" } else { "2040 GB" },
+            if vhdx { "VHDX" } else { "VHD" })))
+    }
+    Ok(())
+}
+
+// Windows paths are case insensitive
+fn same_path(a: &Path, b: &Path) -> bool {
+    a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+}
+
+// Checkpoint disks (.avhd, .avhdx) are differencing disks of the same format as the disk they were taken of
+pub(crate) fn is_vhdx(path: &Path) -> Result<bool> {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()) {
+        Some(ref e) if e == "vhdx" || e == "avhdx" => Ok(true),
+        Some(ref e) if e == "vhd" || e == "avhd" => Ok(false),
+        _ => Err(HypervError::new("Virtual disk path must end in .vhd, .vhdx, .avhd or .avhdx")),
+    }
+}
+
+// Single quoted so that $ and backticks in the path are taken literally
+fn quoted_path(path: &Path) -> Result<String> {
+    Ok(ps_quote(path.to_str().ok_or_else(|| HypervError::new("Bad path"))?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_format_from_extension() {
+        assert!(is_vhdx(Path::new("C:\\Disks\\web.vhdx")).unwrap());
+        assert!(!is_vhdx(Path::new("C:\\Disks\\WEB.VHD")).unwrap());
+        assert!(is_vhdx(Path::new("C:\\Disks\\web_6F1C0B3E-1A2B-4C5D-8E9F-0A1B2C3D4E5F.avhdx")).unwrap());
+        assert!(!is_vhdx(Path::new("C:\\Disks\\web_6F1C0B3E-1A2B-4C5D-8E9F-0A1B2C3D4E5F.avhd")).unwrap());
+        assert!(is_vhdx(Path::new("C:\\Disks\\web.img")).is_err());
+        assert!(is_vhdx(Path::new("C:\\Disks\\web")).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+// Serves VM queries straight from the root\virtualization\v2 WMI namespace, skipping the PowerShell
+// process. Only queries whose answer Msvm_ComputerSystem holds are served here. Everything else, and
+// everything while a remote host, constrained endpoint or alternate account is in use, still goes
+// through PowerShell
+use crate::{Hyperv, HypervError, Result, Vm, VmId, VmProp, VmState, queue, remote};
+use serde_derive::Deserialize;
+use std::time::Duration;
+use wmi::{COMLibrary, WMIConnection};
+
+const NAMESPACE: &str = "root\\virtualization\\v2";
+const SUPPORTED_PROPS: [VmProp; 2] = [VmProp::State, VmProp::Uptime];
+
+#[derive(Deserialize)]
+#[serde(rename = "Msvm_ComputerSystem")]
+#[serde(rename_all = "PascalCase")]
+struct MsvmComputerSystem {
+    name: String,
+    element_name: String,
+    enabled_state: u16,
+    on_time_in_milliseconds: Option<u64>,
+}
+
+impl MsvmComputerSystem {
+    fn into_vm(self) -> Result<Vm> {
+        Ok(Vm {
+            id: VmId::parse(&self.name)?,
+            name: self.element_name,
+            state: Some(state_of(self.enabled_state)),
+            status: None,
+            uptime: Some(Duration::from_millis(self.on_time_in_milliseconds.unwrap_or(0))),
+            generation: None,
+            processor_count: None,
+            memory_assigned: None,
+            version: None,
+        })
+    }
+}
+
+// Msvm_ComputerSystem.EnabledState. The 327xx values are Hyper-V's own transitional states
+fn state_of(enabled_state: u16) -> VmState {
+    match enabled_state {
+        2 => VmState::Running,
+        3 => VmState::Off,
+        4 | 32774 => VmState::Stopping,
+        6 | 32769 => VmState::Saved,
+        9 | 32768 => VmState::Paused,
+        10 | 32770 => VmState::Starting,
+        11 => VmState::Reset,
+        32773 => VmState::Saving,
+        32776 => VmState::Pausing,
+        32777 => VmState::Resuming,
+        s => VmState::Other(s.to_string()),
+    }
+}
+
+// None if the query can't be served from WMI
+pub(crate) fn get_vms(props: &[VmProp], id: Option<&VmId>) -> Result<Option<Vec<Vm>>> {
+    if !props.iter().all(|p| SUPPORTED_PROPS.contains(p))
+        || !remote::is_local()
+        || Hyperv::constrained_endpoint().is_some()
+        || crate::config::runs_as_other_account() {
+        return Ok(None)
+    }
+
+    // Fails on threads whose COM apartment was already initialized differently, e.g. as STA by a UI
+    let com = match COMLibrary::new() {
+        Ok(com) => com,
+        Err(_) => return Ok(None),
+    };
+    let _slot = queue::acquire_slot();
+    let connection = WMIConnection::with_namespace_path(NAMESPACE, com).map_err(wmi_error)?;
+    let mut query = "SELECT Name, ElementName, EnabledState, OnTimeInMilliseconds FROM Msvm_ComputerSystem".to_owned();
+    if let Some(id) = id {
+        query.push_str(&format!(" WHERE Name = '{}'", id.to_string().to_uppercase()));
+    }
+    let systems: Vec<MsvmComputerSystem> = connection.raw_query(&query).map_err(wmi_error)?;
+    // The host itself is an Msvm_ComputerSystem too. Its Name is the computer name where VMs have their
+    // Id. Caption tells them apart as well but is localized
+    systems.into_iter()
+        .filter(|s| VmId::parse(&s.name).is_ok())
+        .map(MsvmComputerSystem::into_vm)
+        .collect::<Result<_>>()
+        .map(Some)
+}
+
+fn wmi_error(e: wmi::WMIError) -> HypervError {
+    HypervError::new(format!("WMI query failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_enabled_states() {
+        assert_eq!(state_of(2), VmState::Running);
+        assert_eq!(state_of(3), VmState::Off);
+        assert_eq!(state_of(32769), VmState::Saved);
+        assert_eq!(state_of(1), VmState::Other("1".to_owned()));
+    }
+}